@@ -41,6 +41,39 @@ namespace RACErsLedger.DataTypes
     }
 
 
+    [Serializable]
+    public class WelcomeEvent : LedgerEventBase
+    {
+        // Bump this when an event changes in a way the lamprey would need to know about, and bump PROTOCOL_VERSION
+        // in racers-ledger-datatypes along with it.
+        public const int CurrentProtocolVersion = 1;
+
+        public string Msg { get; }
+        public int ProtocolVersion { get; }
+        public string ModVersion { get; }
+        public string GameBuild { get; }
+        // Keep in sync with MOD_EVENT_TYPES in racers-ledger-datatypes
+        public string[] SupportedEventTypes { get; }
+
+        public WelcomeEvent(string msg, string modVersion, string gameBuild) : base()
+        {
+            Msg = msg;
+            ProtocolVersion = CurrentProtocolVersion;
+            ModVersion = modVersion;
+            GameBuild = gameBuild;
+            SupportedEventTypes = new[]
+            {
+                "welcomeEvent",
+                "shiftSalvageLogEntry",
+                "gameStateChangedEvent",
+                "startShiftEvent",
+                "endShiftEvent",
+                "setRACEInfoEvent",
+                "timeTickEvent",
+            };
+        }
+    }
+
     [Serializable]
     public class StartShiftEvent : LedgerEventBase
     {
@@ -49,6 +82,14 @@ namespace RACErsLedger.DataTypes
     [Serializable]
     public class EndShiftEvent : LedgerEventBase
     {
+        // Why the shift ended: "complete", "abort", or "unknown (<previous state> -> <state>)". See ExitCause in
+        // racers-ledger-datatypes.
+        public string ExitCause { get; }
+
+        public EndShiftEvent(string exitCause) : base()
+        {
+            ExitCause = exitCause;
+        }
     }
     [Serializable]
     public class GameStateChangedEvent : LedgerEventBase
@@ -17,12 +17,13 @@ namespace RACErsLedger
 
     public class EventBroadcastServer : WebSocketBehavior
     {
-        // TODO(sariya): allow clients to filter the types of messages they receive (i.e. only new shifts, for example)
-        // though actually maybe this should be in the lamprey proxy instead!
+        // clients that only want some types of messages (i.e. only new shifts, for example) can subscribe to just those
+        // through the lamprey proxy, so we don't need to care about it here
         protected override void OnOpen()
         {
             Plugin.Log(LogLevel.Info, $"new client connected: {Context.UserEndPoint} (session {ID})");
-            Send("{\"msg\":\"hello new client!\",\"type\":\"welcomeEvent\"}");
+            var welcome = new WelcomeEvent("hello new client!", Plugin.Version, UnityEngine.Application.version);
+            Send(JsonConvert.SerializeObject(welcome, LampreyManager.JsonSerializerSettings));
         }
 
         protected override void OnError(ErrorEventArgs e)
@@ -38,7 +39,7 @@ namespace RACErsLedger
         private readonly bool _lampreyListenOnAllInterfaces;
         private Process _lampreyProcess;
         [CanBeNull] private WebSocketServer _server;
-        private static readonly JsonSerializerSettings JsonSerializerSettings = new JsonSerializerSettings
+        internal static readonly JsonSerializerSettings JsonSerializerSettings = new JsonSerializerSettings
         {
             ContractResolver = new DefaultContractResolver()
             {
@@ -63,7 +64,7 @@ namespace RACErsLedger
             try
             {
                 var exposeLampreyFlag = _lampreyListenOnAllInterfaces ? "--expose" : "";
-                _lampreyProcess = Process.Start(Path.Combine(Paths.PluginPath, "RACErsLedger", "racers-ledger-lamprey.exe"), $"{_websocketListenPort} {_lampreyListenPort} {exposeLampreyFlag}");
+                _lampreyProcess = Process.Start(Path.Combine(Paths.PluginPath, "RACErsLedger", "racers-ledger-lamprey.exe"), $"connect {_websocketListenPort} {_lampreyListenPort} {exposeLampreyFlag}");
             } catch (Exception e)
             {
                 Plugin.Log(LogLevel.Error, $"failed to launch lamprey! {e}");
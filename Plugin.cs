@@ -8,11 +8,12 @@ using System.IO;
 
 namespace RACErsLedger
 {
-    [BepInPlugin(UUID, "RACErs Ledger", "1.8.0.0")]
+    [BepInPlugin(UUID, "RACErs Ledger", Version)]
     [BepInProcess("Shipbreaker.exe")]
     public class Plugin : BaseUnityPlugin
     {
         private const string UUID = "dev.sariya.racersledger";
+        public const string Version = "1.8.0.0";
         private static ManualLogSource _logSource;
         public static StateManager StateManager { get; private set; }
         public static ConfigEntry<string> ConfigDataFolder { get; private set; }
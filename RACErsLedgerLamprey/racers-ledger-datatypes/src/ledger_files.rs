@@ -0,0 +1,528 @@
+// Reading the files the mod writes at the end of every shift (see StateManager.EndShift in racers-ledger/StateManager.cs)
+// back in, so shifts from before the lamprey was keeping track of anything aren't lost to it.
+//
+// These files were written with the game's current culture, so only ones from cultures that write numbers like
+// 1234.5 (and not 1234,5) can be read back reliably. Anything we can't make sense of is an error, not a guess.
+use chrono::prelude::*;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{ExitCause, SalvageEvent};
+
+/// Columns `ShiftLog.WriteSalvageLedger` writes, in order.
+pub const LEDGER_CSV_HEADER: &str =
+    "objectName,mass,categories,salvagedBy,value,massBasedValue,destroyed,gameTime,epochTimeMs";
+
+#[derive(Debug)]
+pub enum LedgerFileError {
+    Io(std::io::Error),
+    /// The file name isn't `[RACE<n>-]yyyyMMddTHHmmss_ledger.csv`
+    FileName(String),
+    /// Something's off with a line in the `_ledger.csv` (1-based line number)
+    Ledger {
+        line: usize,
+        message: String,
+    },
+    /// Something's off with a line in the `_summary.txt` (1-based line number)
+    Summary {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for LedgerFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerFileError::Io(e) => write!(f, "{e}"),
+            LedgerFileError::FileName(name) => write!(
+                f,
+                "{name:?} doesn't look like [RACE<n>-]yyyyMMddTHHmmss_ledger.csv"
+            ),
+            LedgerFileError::Ledger { line, message } => {
+                write!(f, "ledger line {line}: {message}")
+            }
+            LedgerFileError::Summary { line, message } => {
+                write!(f, "summary line {line}: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LedgerFileError {}
+
+impl From<std::io::Error> for LedgerFileError {
+    fn from(e: std::io::Error) -> Self {
+        LedgerFileError::Io(e)
+    }
+}
+
+/// What `[RACE<n>-]yyyyMMddTHHmmss` tells us: when the shift started (local time, same as the game wrote it), and
+/// which RACE it was if it was one. `n` is the RACE version + 1.
+pub fn parse_file_name_base(base: &str) -> Option<(NaiveDateTime, Option<i64>)> {
+    let (race_version, timestamp) = match base.strip_prefix("RACE") {
+        Some(rest) => {
+            let (n, timestamp) = rest.split_once('-')?;
+            (Some(n.parse::<i64>().ok()? - 1), timestamp)
+        }
+        None => (None, base),
+    };
+    let started = NaiveDateTime::parse_from_str(timestamp, "%Y%m%dT%H%M%S").ok()?;
+    Some((started, race_version))
+}
+
+fn parse_bool(field: &str) -> Option<bool> {
+    // C# writes bools as True/False
+    match field.to_ascii_lowercase().as_str() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Turn the contents of a `_ledger.csv` back into the `ShiftSalvageLogEntry`s it was written from.
+///
+/// The mod doesn't quote anything, so object names with commas in them come out as extra columns. Every other column
+/// is a number, a bool or a `;`-separated list of categories, so those extra columns get glued back onto the name.
+pub fn parse_ledger_csv(contents: &str) -> Result<Vec<SalvageEvent>, LedgerFileError> {
+    let mut lines = contents.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim_start_matches('\u{feff}').trim() == LEDGER_CSV_HEADER => {}
+        _ => {
+            return Err(LedgerFileError::Ledger {
+                line: 1,
+                message: format!("expected the header to be {LEDGER_CSV_HEADER}"),
+            })
+        }
+    }
+    let column_count = LEDGER_CSV_HEADER.split(',').count();
+    let mut entries = vec![];
+    for (index, line) in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let error = |message: String| LedgerFileError::Ledger {
+            line: index + 1,
+            message,
+        };
+        let mut fields: Vec<&str> = line.rsplitn(column_count, ',').collect();
+        if fields.len() != column_count {
+            let found = fields.len();
+            return Err(error(format!(
+                "expected {column_count} columns, got {found}"
+            )));
+        }
+        fields.reverse();
+        let number = |column: usize| -> Result<f64, LedgerFileError> {
+            let field = fields[column];
+            field
+                .parse()
+                .map_err(|_| error(format!("{field:?} isn't a number")))
+        };
+        let boolean = |column: usize| -> Result<bool, LedgerFileError> {
+            let field = fields[column];
+            parse_bool(field).ok_or_else(|| error(format!("{field:?} isn't True or False")))
+        };
+        let epoch_time_ms = fields[8];
+        let epoch_time_ms: i64 = epoch_time_ms
+            .parse()
+            .map_err(|_| error(format!("{epoch_time_ms:?} isn't a timestamp")))?;
+        let system_time = Utc
+            .timestamp_millis_opt(epoch_time_ms)
+            .single()
+            .ok_or_else(|| error(format!("{epoch_time_ms} is out of range")))?;
+        entries.push(SalvageEvent::ShiftSalvageLogEntry {
+            object_name: fields[0].to_string(),
+            mass: number(1)?,
+            categories: fields[2]
+                .split(';')
+                .filter(|category| !category.is_empty())
+                .map(String::from)
+                .collect(),
+            salvaged_by: fields[3].to_string(),
+            value: number(4)?,
+            mass_based_value: boolean(5)?,
+            destroyed: boolean(6)?,
+            game_time: number(7)? as f32,
+            system_time,
+        });
+    }
+    Ok(entries)
+}
+
+/// RACE info as `ShiftLog.WriteShiftSummary` writes it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryRaceInfo {
+    pub seed: i64,
+    pub version: i64,
+    pub start_date_utc: String,
+    pub max_total_value: i64,
+    pub max_salvage_mass: i64,
+}
+
+/// The parts of a `_summary.txt` that aren't in the `_ledger.csv` already.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShiftSummaryFile {
+    /// `EndedBy`, i.e. `complete` or `abort`. Summaries from before the mod kept track of it don't have one.
+    pub exit_cause: Option<String>,
+    pub duration: Option<chrono::Duration>,
+    pub race_info: Option<SummaryRaceInfo>,
+}
+
+/// Parse a C# `TimeSpan` as `ToString()` writes it: `[-][d.]hh:mm:ss[.fffffff]`
+fn parse_time_span(s: &str) -> Option<chrono::Duration> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let parts: Vec<&str> = s.split(':').collect();
+    let (hours, minutes, seconds) = match parts.as_slice() {
+        [hours, minutes, seconds] => (*hours, *minutes, *seconds),
+        _ => return None,
+    };
+    let (days, hours) = hours.split_once('.').unwrap_or(("0", hours));
+    let (seconds, fraction) = seconds.split_once('.').unwrap_or((seconds, "0"));
+    let (days, hours, minutes, seconds): (i64, i64, i64, i64) = (
+        days.parse().ok()?,
+        hours.parse().ok()?,
+        minutes.parse().ok()?,
+        seconds.parse().ok()?,
+    );
+    // ticks are 100ns, but be lenient about how many digits there are
+    let nanoseconds: i64 = format!("{fraction:0<9}").get(..9)?.parse().ok()?;
+    let duration = chrono::Duration::days(days)
+        + chrono::Duration::hours(hours)
+        + chrono::Duration::minutes(minutes)
+        + chrono::Duration::seconds(seconds)
+        + chrono::Duration::nanoseconds(nanoseconds);
+    Some(if negative { -duration } else { duration })
+}
+
+/// Parse a whole number C# formatted with `F` or `N`, i.e. `$1234567.00` or `1,234,567.00kg`.
+fn parse_formatted_integer(s: &str) -> Option<i64> {
+    let s = s.trim().trim_start_matches('$').trim_end_matches("kg");
+    // drop the decimals (always .00, these are ints), then any group separators
+    let s = match s.char_indices().rev().nth(2) {
+        Some((index, '.')) | Some((index, ',')) => &s[..index],
+        _ => s,
+    };
+    s.chars()
+        .filter(|c| !matches!(c, ',' | '.' | ' ' | '\'' | '\u{a0}' | '\u{202f}'))
+        .collect::<String>()
+        .parse()
+        .ok()
+}
+
+/// Turn the contents of a `_summary.txt` back into what it says about the shift.
+pub fn parse_summary(contents: &str) -> Result<ShiftSummaryFile, LedgerFileError> {
+    let mut summary = ShiftSummaryFile::default();
+    let mut is_race = false;
+    let (mut seed, mut version, mut start_date_utc, mut max_total_value, mut max_salvage_mass) =
+        (None, None, None, None, None);
+    for (index, line) in contents.lines().enumerate() {
+        let error = |message: String| LedgerFileError::Summary {
+            line: index + 1,
+            message,
+        };
+        let line = line.trim_start_matches('\u{feff}');
+        if line.starts_with("Top 5") {
+            // the rest is the destroyed objects, which the ledger has all of anyways
+            break;
+        }
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key, value.trim()),
+            None => continue,
+        };
+        match key {
+            "EndedBy" if !value.is_empty() => summary.exit_cause = Some(value.to_string()),
+            "Duration" => {
+                summary.duration = Some(
+                    parse_time_span(value)
+                        .ok_or_else(|| error(format!("{value:?} isn't a duration")))?,
+                )
+            }
+            "RACE?" => {
+                is_race = parse_bool(value)
+                    .ok_or_else(|| error(format!("{value:?} isn't True or False")))?
+            }
+            "Seed" => {
+                seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| error(format!("{value:?} isn't a seed")))?,
+                )
+            }
+            "Version" => {
+                // "4 (probably week 5)"
+                let number = value.split_whitespace().next().unwrap_or_default();
+                version = Some(
+                    number
+                        .parse()
+                        .map_err(|_| error(format!("{value:?} isn't a version")))?,
+                )
+            }
+            "Start date" => start_date_utc = Some(value.to_string()),
+            "Maximum possible salvage" => {
+                max_total_value = Some(
+                    parse_formatted_integer(value)
+                        .ok_or_else(|| error(format!("{value:?} isn't a value")))?,
+                )
+            }
+            "Total mass" => {
+                max_salvage_mass = Some(
+                    parse_formatted_integer(value)
+                        .ok_or_else(|| error(format!("{value:?} isn't a mass")))?,
+                )
+            }
+            // Started/Ended are in whatever format the game's culture likes, the file name has the start time anyways
+            _ => {}
+        }
+    }
+    if is_race {
+        summary.race_info = match (
+            seed,
+            version,
+            start_date_utc,
+            max_total_value,
+            max_salvage_mass,
+        ) {
+            (
+                Some(seed),
+                Some(version),
+                Some(start_date_utc),
+                Some(max_total_value),
+                Some(max_salvage_mass),
+            ) => Some(SummaryRaceInfo {
+                seed,
+                version,
+                start_date_utc,
+                max_total_value,
+                max_salvage_mass,
+            }),
+            _ => {
+                return Err(LedgerFileError::Summary {
+                    line: contents.lines().count(),
+                    message: "it's a RACE, but the RACE info is incomplete".into(),
+                })
+            }
+        };
+    }
+    Ok(summary)
+}
+
+/// One shift, read back from a `_ledger.csv` and (if it's next to it) its `_summary.txt`.
+#[derive(Debug, Clone)]
+pub struct ImportedShift {
+    pub ledger_path: PathBuf,
+    /// From the file name, which the game wrote in local time
+    pub started: DateTime<Utc>,
+    /// Only known if there was a summary
+    pub ended: Option<DateTime<Utc>>,
+    /// RACE version (not week number) from the file name, if it was a RACE
+    pub race_version: Option<i64>,
+    /// Whatever the summary had to say, if there was one
+    pub summary: Option<ShiftSummaryFile>,
+    /// Every `ShiftSalvageLogEntry` in the ledger
+    pub entries: Vec<SalvageEvent>,
+}
+
+impl ImportedShift {
+    /// Read `<base>_ledger.csv`, and `<base>_summary.txt` too if there is one.
+    pub fn read(ledger_path: &Path) -> Result<ImportedShift, LedgerFileError> {
+        let file_name = ledger_path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .unwrap_or_default();
+        let base = file_name
+            .strip_suffix("_ledger.csv")
+            .ok_or_else(|| LedgerFileError::FileName(file_name.to_string()))?;
+        let (started, race_version) = parse_file_name_base(base)
+            .ok_or_else(|| LedgerFileError::FileName(file_name.to_string()))?;
+        let started = Local
+            .from_local_datetime(&started)
+            .earliest()
+            .ok_or_else(|| LedgerFileError::FileName(file_name.to_string()))?
+            .with_timezone(&Utc);
+        let entries = parse_ledger_csv(&std::fs::read_to_string(ledger_path)?)?;
+        let summary_path = ledger_path.with_file_name(format!("{base}_summary.txt"));
+        let summary = match std::fs::read_to_string(summary_path) {
+            Ok(contents) => Some(parse_summary(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let ended = summary
+            .as_ref()
+            .and_then(|summary| summary.duration)
+            .map(|duration| started + duration);
+        Ok(ImportedShift {
+            ledger_path: ledger_path.to_path_buf(),
+            started,
+            ended,
+            race_version,
+            summary,
+            entries,
+        })
+    }
+
+    /// The events the mod would have sent for this shift (the ones we know about, anyways), in order.
+    pub fn events(&self) -> Vec<SalvageEvent> {
+        let mut salvage_events = vec![SalvageEvent::StartShiftEvent {
+            system_time: self.started,
+        }];
+        if let Some(race_info) = self
+            .summary
+            .as_ref()
+            .and_then(|summary| summary.race_info.as_ref())
+        {
+            salvage_events.push(SalvageEvent::SetRACEInfoEvent {
+                seed: race_info.seed,
+                version: race_info.version,
+                start_date_utc: race_info.start_date_utc.clone(),
+                max_total_value: race_info.max_total_value,
+                max_salvage_mass: race_info.max_salvage_mass,
+                system_time: self.started,
+            });
+        }
+        salvage_events.extend(self.entries.iter().cloned());
+        if let Some(ended) = self.ended {
+            salvage_events.push(SalvageEvent::EndShiftEvent {
+                exit_cause: self
+                    .summary
+                    .as_ref()
+                    .and_then(|summary| summary.exit_cause.clone())
+                    .map(ExitCause::from),
+                system_time: ended,
+            });
+        }
+        salvage_events
+    }
+}
+
+#[test]
+fn test_parse_file_name_base() {
+    let started = NaiveDate::from_ymd_opt(2021, 7, 4)
+        .unwrap()
+        .and_hms_opt(12, 34, 56)
+        .unwrap();
+    assert_eq!(
+        parse_file_name_base("RACE5-20210704T123456"),
+        Some((started, Some(4)))
+    );
+    assert_eq!(
+        parse_file_name_base("20210704T123456"),
+        Some((started, None))
+    );
+    assert_eq!(parse_file_name_base("RACE-20210704T123456"), None);
+    assert_eq!(parse_file_name_base("yesterday"), None);
+}
+
+#[test]
+fn test_parse_ledger_csv() {
+    let csv = format!(
+        "{LEDGER_CSV_HEADER}\r\n\
+         Hull Plate,1234.500,Ferrous;Salvage,Furnace,567.89,True,False,12.3,1625402096000\r\n\
+         Thing, With A Comma,1.000,,Processor,0.00,False,True,13.0,1625402097000\r\n"
+    );
+    let entries = parse_ledger_csv(&csv).unwrap();
+    assert_eq!(entries.len(), 2);
+    match &entries[0] {
+        SalvageEvent::ShiftSalvageLogEntry {
+            object_name,
+            mass,
+            categories,
+            salvaged_by,
+            value,
+            mass_based_value,
+            destroyed,
+            game_time,
+            system_time,
+        } => {
+            assert_eq!(object_name, "Hull Plate");
+            assert_eq!(*mass, 1234.5);
+            assert_eq!(
+                categories,
+                &vec!["Ferrous".to_string(), "Salvage".to_string()]
+            );
+            assert_eq!(salvaged_by, "Furnace");
+            assert_eq!(*value, 567.89);
+            assert!(*mass_based_value);
+            assert!(!*destroyed);
+            assert_eq!(*game_time, 12.3);
+            assert_eq!(system_time.timestamp_millis(), 1625402096000);
+        }
+        other => panic!("expected a salvage entry, got {:?}", other),
+    }
+    match &entries[1] {
+        SalvageEvent::ShiftSalvageLogEntry {
+            object_name,
+            categories,
+            destroyed,
+            ..
+        } => {
+            assert_eq!(object_name, "Thing, With A Comma");
+            assert!(categories.is_empty());
+            assert!(*destroyed);
+        }
+        other => panic!("expected a salvage entry, got {:?}", other),
+    }
+
+    let bad = format!("{LEDGER_CSV_HEADER}\nSeat,1.0,,Furnace,1.0,Maybe,False,1.0,0\n");
+    assert!(matches!(
+        parse_ledger_csv(&bad),
+        Err(LedgerFileError::Ledger { line: 2, .. })
+    ));
+    assert!(parse_ledger_csv("not,a,ledger\n").is_err());
+}
+
+#[test]
+fn test_parse_summary() {
+    let summary = parse_summary(
+        "Started: 7/4/2021 12:34:56 PM\n\
+         Ended: 7/4/2021 12:49:56 PM\n\
+         EndedBy: complete\n\
+         Duration: 00:15:00.1234567\n\
+         Total value salvaged: $1234.567\n\
+         Total value destroyed: $0.000\n\
+         RACE?: True\n\
+         \n\
+         RACE Info\n\
+         --------------------------------------\n\
+         Seed: 1234\n\
+         Version: 4 (probably week 5)\n\
+         Start date: 2021-07-01T00:00:00Z\n\
+         Maximum possible salvage: $1234567.00\n\
+         Total mass: 234,567.00kg\n\
+         --------------------------------------\n\
+         Top 5 most valuable destroyed objects:\n\
+         Seat: whatever\n",
+    )
+    .unwrap();
+    assert_eq!(summary.exit_cause.as_deref(), Some("complete"));
+    assert_eq!(
+        summary.duration,
+        Some(chrono::Duration::minutes(15) + chrono::Duration::nanoseconds(123_456_700))
+    );
+    assert_eq!(
+        summary.race_info,
+        Some(SummaryRaceInfo {
+            seed: 1234,
+            version: 4,
+            start_date_utc: "2021-07-01T00:00:00Z".into(),
+            max_total_value: 1_234_567,
+            max_salvage_mass: 234_567,
+        })
+    );
+
+    let not_a_race = parse_summary("EndedBy: abort\nDuration: 1.02:03:04\nRACE?: False\n").unwrap();
+    assert_eq!(
+        not_a_race.duration,
+        Some(
+            chrono::Duration::days(1)
+                + chrono::Duration::hours(2)
+                + chrono::Duration::minutes(3)
+                + chrono::Duration::seconds(4)
+        )
+    );
+    assert!(not_a_race.race_info.is_none());
+}
@@ -1,14 +1,58 @@
 // Datatypes used in RACErs Ledger Extended Universe. Should generally be kept in sync with racers-ledger/DataTypes/DataTypes.cs.
 use chrono::prelude::*;
 use colored::Colorize;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 
+/// Reading the mod's own `_ledger.csv` and `_summary.txt` files back in.
+pub mod ledger_files;
+
+/// What the lamprey works out about a shift once it's over.
+pub mod shift_summary;
+
+use shift_summary::ShiftSummary;
+
+/// Version of the events the mod sends. Bump it (here and WelcomeEvent.CurrentProtocolVersion in DataTypes.cs) whenever
+/// they change in a way something reading them could trip over.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// `type`s of every event the mod can send as of `PROTOCOL_VERSION`. Keep in sync with WelcomeEvent in DataTypes.cs.
+pub const MOD_EVENT_TYPES: &[&str] = &[
+    "welcomeEvent",
+    "shiftSalvageLogEntry",
+    "gameStateChangedEvent",
+    "startShiftEvent",
+    "endShiftEvent",
+    "setRACEInfoEvent",
+    "timeTickEvent",
+];
+
+// `remote = "Self"` makes the derives plain `SalvageEvent::serialize`/`deserialize` functions that only know the typed
+// events, so that the real impls below can deal with `Unknown` and still tell "not an event we know" apart from "an
+// event we know, but broken" (see `SalvageEvent::malformed`).
 #[derive(Deserialize, Serialize, Debug, Clone)]
-#[serde(rename_all = "camelCase", tag = "type")]
+#[serde(rename_all = "camelCase", tag = "type", remote = "Self")]
 pub enum SalvageEvent {
+    // Everything but msg is missing from mods older than protocol version 1.
     #[serde(rename_all = "camelCase")]
-    WelcomeEvent { msg: String },
+    WelcomeEvent {
+        msg: String,
+        // the mod's PROTOCOL_VERSION
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        protocol_version: Option<u32>,
+        // RACErs Ledger version, i.e. 1.8.0.0
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mod_version: Option<String>,
+        // Hardspace: Shipbreaker's build (Unity's Application.version)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        game_build: Option<String>,
+        // every event type the mod might send
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        supported_event_types: Option<Vec<String>>,
+        // System time when the client connected
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        system_time: Option<DateTime<Utc>>,
+    },
     #[serde(rename_all = "camelCase")]
     ShiftSalvageLogEntry {
         // Localized object name
@@ -46,6 +90,9 @@ pub enum SalvageEvent {
     },
     #[serde(rename_all = "camelCase")]
     EndShiftEvent {
+        // Why the shift ended, missing from mods that didn't send it yet
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        exit_cause: Option<ExitCause>,
         // System time when shift ended
         system_time: DateTime<Utc>,
     },
@@ -74,6 +121,269 @@ pub enum SalvageEvent {
         // System time when this Tick was registered
         system_time: DateTime<Utc>,
     },
+    // The variants below are never sent by the mod, the lamprey synthesizes them for its own clients.
+    #[serde(rename_all = "camelCase")]
+    LampreyWelcomeEvent {
+        // the lamprey's crate version
+        lamprey_version: String,
+        // version of the lamprey's HTTP API, i.e. 0 for /api/v0
+        api_version: u32,
+        // the PROTOCOL_VERSION the lamprey was built with, what it expects from the mod and speaks to clients
+        protocol_version: u32,
+        // what /api/v0/status would say right now
+        state: serde_json::Value,
+        // System time when the client connected
+        system_time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    LampreyDroppedEventsEvent {
+        // how many events the lamprey dropped for this client since the last time it said so
+        dropped: u64,
+        // how many it dropped for this client since it connected
+        total_dropped: u64,
+        // System time when the client got told
+        system_time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    UpstreamConnectedEvent {
+        // System time when the lamprey (re)connected to the mod websocket
+        system_time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    UpstreamDisconnectedEvent {
+        // Why the lamprey thinks it lost the mod websocket
+        reason: String,
+        // the close code the mod said goodbye with, missing if the connection just dropped (network trouble, the
+        // game crashing...)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        close_code: Option<u16>,
+        // System time when the connection dropped
+        system_time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    RacePaceEvent {
+        // the TimeTickEvent this is about
+        current_time: f64,
+        max_time: f64,
+        // salvaged (not destroyed) so far this shift
+        value: f64,
+        mass: f64,
+        // where value and mass end up by max_time if we keep salvaging at the rate we have been
+        projected_value: f64,
+        projected_mass: f64,
+        // value and mass so far as a percentage of the RACE's max_total_value and max_salvage_mass, missing if the
+        // max is 0
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        percent_of_max_total_value: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        percent_of_max_salvage_mass: Option<f64>,
+        // System time of the TimeTickEvent
+        system_time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    GhostDeltaEvent {
+        // id of the stored shift we're racing against, i.e. for /api/v0/shifts/<id>
+        ghost_shift_id: i64,
+        // the TimeTickEvent's current time
+        game_time: f64,
+        // salvaged (not destroyed) so far, by us and by the ghost at the same game time
+        value: f64,
+        ghost_value: f64,
+        // value - ghost_value, so positive when we're ahead
+        value_delta: f64,
+        // what the ghost had salvaged by the end of its shift
+        ghost_final_value: f64,
+        // System time of the TimeTickEvent
+        system_time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    GhostSplitEvent {
+        // id of the stored shift we're racing against
+        ghost_shift_id: i64,
+        // we've now salvaged as much value in this category as the ghost did all shift
+        category: String,
+        // when we got there, and when the ghost did
+        game_time: f64,
+        ghost_game_time: f64,
+        // game_time - ghost_game_time, so negative when we're ahead
+        time_delta: f64,
+        // our salvaged value now minus the ghost's when it got there, so positive when we're ahead
+        value_delta: f64,
+        // System time of the ShiftSalvageLogEntry that got us there
+        system_time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    ShiftSummaryEvent {
+        // how the shift that just ended went (boxed, it's a lot bigger than every other event)
+        summary: Box<ShiftSummary>,
+        // System time when the lamprey saw the shift end
+        system_time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    LampreyShutdownEvent {
+        // why the lamprey is going away
+        cause: ShutdownCause,
+        // the close code and reason proxy clients are about to get
+        code: u16,
+        reason: String,
+        // System time when the lamprey started shutting down
+        system_time: DateTime<Utc>,
+    },
+    // Anything the mod sends that isn't one of the above (a new event type the lamprey doesn't know about yet, or a
+    // known one that's missing fields), exactly as it was sent. Only ever made by deserializing once nothing else fits.
+    #[serde(skip)]
+    Unknown(serde_json::Value),
+}
+
+impl Serialize for SalvageEvent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            SalvageEvent::Unknown(raw) => raw.serialize(serializer),
+            salvage_event => SalvageEvent::serialize(salvage_event, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SalvageEvent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = serde_json::Value::deserialize(deserializer)?;
+        Ok(SalvageEvent::deserialize(&raw).unwrap_or(SalvageEvent::Unknown(raw)))
+    }
+}
+
+/// Why the lamprey shut down, see `SalvageEvent::LampreyShutdownEvent`. If the lamprey outright crashes, nobody gets
+/// told anything: the connection just goes away without a close frame.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ShutdownCause {
+    /// The mod said goodbye, i.e. the game exited.
+    GameExited,
+    /// A `replay` ran out of events.
+    ReplayFinished,
+    /// Somebody asked the lamprey to stop (Ctrl-C, SIGTERM...).
+    Stopped,
+    /// Whatever was feeding the lamprey events fell over.
+    Crashed,
+}
+
+/// Why a shift ended, see `SalvageEvent::EndShiftEvent`. Goes over the wire as whatever string the mod sent, so causes
+/// the lamprey doesn't know about yet aren't lost.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(from = "String", into = "String")]
+pub enum ExitCause {
+    /// `complete`: the shift made it to the summary screen, i.e. the clock ran out (always the case in a RACE) or we
+    /// clocked out.
+    Complete,
+    /// `abort`: abandoned from the pause menu.
+    Abort,
+    /// Anything else, exactly as the mod said it. The mod says `unknown (<previous state> -> <state>)` for game state
+    /// changes it didn't expect to end a shift.
+    Unknown(String),
+}
+
+impl ExitCause {
+    /// `complete`, `abort` or `unknown`, for when the whole string would be too much (i.e. as a metrics label).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ExitCause::Complete => "complete",
+            ExitCause::Abort => "abort",
+            ExitCause::Unknown(_) => "unknown",
+        }
+    }
+}
+
+impl From<String> for ExitCause {
+    fn from(exit_cause: String) -> Self {
+        match exit_cause.as_str() {
+            "complete" => ExitCause::Complete,
+            "abort" => ExitCause::Abort,
+            _ => ExitCause::Unknown(exit_cause),
+        }
+    }
+}
+
+impl From<ExitCause> for String {
+    fn from(exit_cause: ExitCause) -> Self {
+        match exit_cause {
+            ExitCause::Unknown(exit_cause) => exit_cause,
+            known => known.kind().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ExitCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitCause::Unknown(exit_cause) => write!(f, "{exit_cause}"),
+            known => write!(f, "{}", known.kind()),
+        }
+    }
+}
+
+impl SalvageEvent {
+    /// The `type` tag this event gets when serialized, i.e. `shiftSalvageLogEntry`. For `Unknown` events, whatever
+    /// `type` they came with (or `unknown` if they didn't have one).
+    pub fn event_type(&self) -> &str {
+        match self {
+            SalvageEvent::WelcomeEvent { .. } => "welcomeEvent",
+            SalvageEvent::ShiftSalvageLogEntry { .. } => "shiftSalvageLogEntry",
+            SalvageEvent::GameStateChangedEvent { .. } => "gameStateChangedEvent",
+            SalvageEvent::StartShiftEvent { .. } => "startShiftEvent",
+            SalvageEvent::EndShiftEvent { .. } => "endShiftEvent",
+            SalvageEvent::SetRACEInfoEvent { .. } => "setRACEInfoEvent",
+            SalvageEvent::TimeTickEvent { .. } => "timeTickEvent",
+            SalvageEvent::LampreyWelcomeEvent { .. } => "lampreyWelcomeEvent",
+            SalvageEvent::LampreyDroppedEventsEvent { .. } => "lampreyDroppedEventsEvent",
+            SalvageEvent::UpstreamConnectedEvent { .. } => "upstreamConnectedEvent",
+            SalvageEvent::UpstreamDisconnectedEvent { .. } => "upstreamDisconnectedEvent",
+            SalvageEvent::RacePaceEvent { .. } => "racePaceEvent",
+            SalvageEvent::GhostDeltaEvent { .. } => "ghostDeltaEvent",
+            SalvageEvent::GhostSplitEvent { .. } => "ghostSplitEvent",
+            SalvageEvent::ShiftSummaryEvent { .. } => "shiftSummaryEvent",
+            SalvageEvent::LampreyShutdownEvent { .. } => "lampreyShutdownEvent",
+            SalvageEvent::Unknown(raw) => raw
+                .get("type")
+                .and_then(|event_type| event_type.as_str())
+                .unwrap_or("unknown"),
+        }
+    }
+
+    /// If this is an `Unknown` event with a `type` the mod sends, why it didn't parse as one: it's a broken event, not a
+    /// new one, and whatever was waiting on it (i.e. the shift ending) won't have heard about it.
+    pub fn malformed(&self) -> Option<serde_json::Error> {
+        match self {
+            SalvageEvent::Unknown(raw) if MOD_EVENT_TYPES.contains(&self.event_type()) => {
+                SalvageEvent::deserialize(raw).err()
+            }
+            _ => None,
+        }
+    }
+
+    /// When did this event happen? Everything but the welcome event knows, and `Unknown` events might.
+    pub fn system_time(&self) -> Option<DateTime<Utc>> {
+        match self {
+            SalvageEvent::WelcomeEvent { system_time, .. } => *system_time,
+            SalvageEvent::Unknown(raw) => raw
+                .get("systemTime")
+                .and_then(|system_time| system_time.as_str())
+                .and_then(|system_time| system_time.parse().ok()),
+            SalvageEvent::ShiftSalvageLogEntry { system_time, .. }
+            | SalvageEvent::GameStateChangedEvent { system_time, .. }
+            | SalvageEvent::StartShiftEvent { system_time }
+            | SalvageEvent::EndShiftEvent { system_time, .. }
+            | SalvageEvent::SetRACEInfoEvent { system_time, .. }
+            | SalvageEvent::TimeTickEvent { system_time, .. }
+            | SalvageEvent::LampreyWelcomeEvent { system_time, .. }
+            | SalvageEvent::LampreyDroppedEventsEvent { system_time, .. }
+            | SalvageEvent::UpstreamConnectedEvent { system_time }
+            | SalvageEvent::UpstreamDisconnectedEvent { system_time, .. }
+            | SalvageEvent::RacePaceEvent { system_time, .. }
+            | SalvageEvent::GhostDeltaEvent { system_time, .. }
+            | SalvageEvent::GhostSplitEvent { system_time, .. }
+            | SalvageEvent::ShiftSummaryEvent { system_time, .. }
+            | SalvageEvent::LampreyShutdownEvent { system_time, .. } => Some(*system_time),
+        }
+    }
 }
 
 // TODO(sariya) should this formatting be part of main.rs's loop instead of here? Right now we're doing coloring and all that fun stuff,
@@ -85,8 +395,25 @@ pub enum SalvageEvent {
 impl fmt::Display for SalvageEvent {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            SalvageEvent::WelcomeEvent { msg } => {
-                write!(f, "{msg}")
+            SalvageEvent::WelcomeEvent {
+                msg,
+                protocol_version,
+                mod_version,
+                game_build,
+                ..
+            } => {
+                write!(f, "{msg}")?;
+                if let Some(protocol_version) = protocol_version {
+                    write!(f, " (protocol v{protocol_version}")?;
+                    if let Some(mod_version) = mod_version {
+                        write!(f, ", RACErs Ledger {mod_version}")?;
+                    }
+                    if let Some(game_build) = game_build {
+                        write!(f, ", game build {game_build}")?;
+                    }
+                    write!(f, ")")?;
+                }
+                Ok(())
             }
             SalvageEvent::ShiftSalvageLogEntry {
                 object_name,
@@ -134,12 +461,19 @@ impl fmt::Display for SalvageEvent {
                     system_time.to_rfc3339_opts(SecondsFormat::Secs, true)
                 )
             }
-            SalvageEvent::EndShiftEvent { system_time } => {
+            SalvageEvent::EndShiftEvent {
+                exit_cause,
+                system_time,
+            } => {
                 write!(
                     f,
                     "({}) ended shift",
                     system_time.to_rfc3339_opts(SecondsFormat::Secs, true)
-                )
+                )?;
+                if let Some(exit_cause) = exit_cause {
+                    write!(f, " ({exit_cause})")?;
+                }
+                Ok(())
             }
             SalvageEvent::SetRACEInfoEvent {
                 seed,
@@ -162,10 +496,154 @@ impl fmt::Display for SalvageEvent {
                     system_time.to_rfc3339_opts(SecondsFormat::Secs, true)
                 )
             }
+            SalvageEvent::LampreyWelcomeEvent {
+                lamprey_version,
+                api_version,
+                protocol_version,
+                system_time,
+                ..
+            } => {
+                write!(
+                    f,
+                    "({}) welcomed a client (lamprey {lamprey_version}, API v{api_version}, protocol v{protocol_version})",
+                    system_time.to_rfc3339_opts(SecondsFormat::Secs, true)
+                )
+            }
+            SalvageEvent::LampreyDroppedEventsEvent {
+                dropped,
+                total_dropped,
+                system_time,
+            } => {
+                write!(
+                    f,
+                    "({}) {}",
+                    system_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    format!("dropped {dropped} events for a slow client ({total_dropped} so far)")
+                        .yellow()
+                )
+            }
+            SalvageEvent::UpstreamConnectedEvent { system_time } => {
+                write!(
+                    f,
+                    "({}) {}",
+                    system_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    "connected to the mod".green()
+                )
+            }
+            SalvageEvent::UpstreamDisconnectedEvent {
+                reason,
+                system_time,
+                ..
+            } => {
+                write!(
+                    f,
+                    "({}) {} ({reason})",
+                    system_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    "lost connection to the mod".red()
+                )
+            }
+            SalvageEvent::RacePaceEvent {
+                projected_value,
+                projected_mass,
+                percent_of_max_total_value,
+                system_time,
+                ..
+            } => {
+                write!(
+                    f,
+                    "({}) on pace to salvage {projected_value:.0} worth ({projected_mass:.0} kg)",
+                    system_time.to_rfc3339_opts(SecondsFormat::Secs, true)
+                )?;
+                if let Some(percent) = percent_of_max_total_value {
+                    write!(f, ", {percent:.1}% of the RACE's max value so far")?;
+                }
+                Ok(())
+            }
+            SalvageEvent::GhostDeltaEvent {
+                value_delta,
+                system_time,
+                ..
+            } => {
+                write!(
+                    f,
+                    "({}) vs the ghost: {}",
+                    system_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    ahead_or_behind(*value_delta, format!("{:.0}", value_delta.abs()))
+                )
+            }
+            SalvageEvent::GhostSplitEvent {
+                category,
+                time_delta,
+                value_delta,
+                system_time,
+                ..
+            } => {
+                write!(
+                    f,
+                    "({}) {category} split: {} ({})",
+                    system_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    ahead_or_behind(-time_delta, format!("{:.1}s", time_delta.abs())),
+                    ahead_or_behind(*value_delta, format!("{:.0}", value_delta.abs()))
+                )
+            }
+            SalvageEvent::ShiftSummaryEvent {
+                summary,
+                system_time,
+            } => {
+                write!(
+                    f,
+                    "({}) {} salvaged {} items worth {} ({} kg), destroyed {} items worth {} in {:.0}s",
+                    system_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    "shift summary:".bold(),
+                    summary.salvaged.items,
+                    summary.salvaged.value,
+                    summary.salvaged.mass,
+                    summary.destroyed.items,
+                    summary.destroyed.value,
+                    summary.duration
+                )?;
+                if let Some(percent) = summary
+                    .race
+                    .as_ref()
+                    .and_then(|race| race.percent_of_max_total_value)
+                {
+                    write!(f, ", {percent:.1}% of the RACE's max value")?;
+                }
+                Ok(())
+            }
+            SalvageEvent::LampreyShutdownEvent {
+                code,
+                reason,
+                system_time,
+                ..
+            } => {
+                write!(
+                    f,
+                    "({}) {} ({code}: {reason})",
+                    system_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    "shutting down".yellow()
+                )
+            }
+            SalvageEvent::Unknown(raw) => {
+                write!(
+                    f,
+                    "{} {raw}",
+                    format!("unrecognized {}:", self.event_type()).yellow()
+                )
+            }
         }
     }
 }
 
+/// "ahead by {by}" in green or "behind by {by}" in red, depending on whether `delta` is in our favour.
+fn ahead_or_behind(delta: f64, by: String) -> colored::ColoredString {
+    if delta >= 0.0 {
+        format!("ahead by {by}").green()
+    } else {
+        format!("behind by {by}").red()
+    }
+}
+
 #[test]
 fn test_send() {
     fn assert_send<T: Send>() {}
@@ -177,3 +655,173 @@ fn test_sync() {
     fn assert_sync<T: Sync>() {}
     assert_sync::<SalvageEvent>();
 }
+
+#[test]
+fn test_event_type_matches_serde_tag() {
+    let now = Utc::now();
+    let salvage_events = vec![
+        SalvageEvent::WelcomeEvent {
+            msg: "hi".into(),
+            protocol_version: Some(PROTOCOL_VERSION),
+            mod_version: None,
+            game_build: None,
+            supported_event_types: None,
+            system_time: None,
+        },
+        SalvageEvent::LampreyWelcomeEvent {
+            lamprey_version: "0.1.0".into(),
+            api_version: 0,
+            protocol_version: PROTOCOL_VERSION,
+            state: serde_json::json!({}),
+            system_time: now,
+        },
+        SalvageEvent::LampreyDroppedEventsEvent {
+            dropped: 1,
+            total_dropped: 2,
+            system_time: now,
+        },
+        SalvageEvent::StartShiftEvent { system_time: now },
+        SalvageEvent::SetRACEInfoEvent {
+            seed: 1,
+            version: 2,
+            start_date_utc: "".into(),
+            max_total_value: 3,
+            max_salvage_mass: 4,
+            system_time: now,
+        },
+        SalvageEvent::UpstreamDisconnectedEvent {
+            reason: "oops".into(),
+            close_code: None,
+            system_time: now,
+        },
+        SalvageEvent::RacePaceEvent {
+            current_time: 1.0,
+            max_time: 2.0,
+            value: 3.0,
+            mass: 4.0,
+            projected_value: 6.0,
+            projected_mass: 8.0,
+            percent_of_max_total_value: None,
+            percent_of_max_salvage_mass: None,
+            system_time: now,
+        },
+        SalvageEvent::GhostSplitEvent {
+            ghost_shift_id: 1,
+            category: "Reactor".into(),
+            game_time: 100.0,
+            ghost_game_time: 90.0,
+            time_delta: 10.0,
+            value_delta: -5.0,
+            system_time: now,
+        },
+        SalvageEvent::ShiftSummaryEvent {
+            summary: Box::new(ShiftSummary {
+                started: now,
+                ended: now,
+                duration: 0.0,
+                game_time: None,
+                salvaged: Default::default(),
+                destroyed: Default::default(),
+                by_salvaged_by: Default::default(),
+                by_category: Default::default(),
+                exit_cause: None,
+                race: None,
+            }),
+            system_time: now,
+        },
+        SalvageEvent::LampreyShutdownEvent {
+            cause: ShutdownCause::Stopped,
+            code: 1001,
+            reason: "bye".into(),
+            system_time: now,
+        },
+    ];
+    for salvage_event in salvage_events {
+        let json = serde_json::to_value(&salvage_event).unwrap();
+        assert_eq!(json["type"], salvage_event.event_type());
+    }
+}
+
+#[test]
+fn test_unknown_events_pass_through() {
+    for json in [
+        // something the mod learned about before we did
+        r#"{"type":"shinyNewEvent","systemTime":"2021-07-04T12:34:56Z","shiny":true}"#,
+        // something we know, but not like this
+        r#"{"type":"endShiftEvent"}"#,
+    ] {
+        let salvage_event: SalvageEvent = serde_json::from_str(json).unwrap();
+        assert!(matches!(salvage_event, SalvageEvent::Unknown(_)));
+        assert_eq!(
+            serde_json::to_value(&salvage_event).unwrap(),
+            serde_json::from_str::<serde_json::Value>(json).unwrap()
+        );
+    }
+    let salvage_event: SalvageEvent =
+        serde_json::from_str(r#"{"type":"shinyNewEvent","systemTime":"2021-07-04T12:34:56Z"}"#)
+            .unwrap();
+    assert_eq!(salvage_event.event_type(), "shinyNewEvent");
+    assert!(salvage_event.system_time().is_some());
+    assert!(salvage_event.malformed().is_none());
+    // a known event that's broken says what's wrong with it instead of passing for a new one
+    let salvage_event: SalvageEvent =
+        serde_json::from_str(r#"{"type":"endShiftEvent","exitCause":42}"#).unwrap();
+    assert_eq!(salvage_event.event_type(), "endShiftEvent");
+    let e = salvage_event.malformed().unwrap();
+    assert!(e.to_string().contains("invalid type"), "{}", e);
+    // and known events are still known
+    let salvage_event: SalvageEvent =
+        serde_json::from_str(r#"{"type":"endShiftEvent","systemTime":"2021-07-04T12:34:56Z"}"#)
+            .unwrap();
+    assert!(matches!(salvage_event, SalvageEvent::EndShiftEvent { .. }));
+}
+
+#[test]
+fn test_exit_cause() {
+    let end_shift = |json: &str| match serde_json::from_str(json).unwrap() {
+        SalvageEvent::EndShiftEvent { exit_cause, .. } => exit_cause,
+        other => panic!("expected an end shift, got {:?}", other),
+    };
+    // from before the mod sent one
+    assert_eq!(
+        end_shift(r#"{"type":"endShiftEvent","systemTime":"2021-07-04T12:34:56Z"}"#),
+        None
+    );
+    assert_eq!(
+        end_shift(
+            r#"{"type":"endShiftEvent","exitCause":"complete","systemTime":"2021-07-04T12:34:56Z"}"#
+        ),
+        Some(ExitCause::Complete)
+    );
+    let odd = r#"{"type":"endShiftEvent","exitCause":"unknown (gameover -> loadinginprogress)","systemTime":"2021-07-04T12:34:56Z"}"#;
+    let exit_cause = end_shift(odd).unwrap();
+    assert_eq!(
+        exit_cause,
+        ExitCause::Unknown("unknown (gameover -> loadinginprogress)".into())
+    );
+    assert_eq!(exit_cause.kind(), "unknown");
+    // and it goes back out exactly as it came in
+    let salvage_event: SalvageEvent = serde_json::from_str(odd).unwrap();
+    assert_eq!(
+        serde_json::to_value(&salvage_event).unwrap(),
+        serde_json::from_str::<serde_json::Value>(odd).unwrap()
+    );
+}
+
+#[test]
+fn test_old_welcome_still_parses() {
+    // what the mod sent before protocol versions were a thing
+    let salvage_event: SalvageEvent =
+        serde_json::from_str(r#"{"msg":"hello new client!","type":"welcomeEvent"}"#).unwrap();
+    match salvage_event {
+        SalvageEvent::WelcomeEvent {
+            msg,
+            protocol_version,
+            ..
+        } => {
+            assert_eq!(msg, "hello new client!");
+            assert_eq!(protocol_version, None);
+        }
+        other => panic!("expected a welcome, got {:?}", other),
+    }
+}
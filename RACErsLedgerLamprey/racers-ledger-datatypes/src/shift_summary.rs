@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ExitCause;
+
+/// How much of something there was.
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Totals {
+    pub items: u64,
+    pub value: f64,
+    pub mass: f64,
+}
+
+impl Totals {
+    pub fn add(&mut self, value: f64, mass: f64) {
+        self.items += 1;
+        self.value += value;
+        self.mass += mass;
+    }
+
+    /// Add everything in `other` in.
+    pub fn merge(&mut self, other: &Totals) {
+        self.items += other.items;
+        self.value += other.value;
+        self.mass += other.mass;
+    }
+}
+
+/// Totals, split by whether we actually got paid for it.
+#[derive(Default, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct SalvageTally {
+    pub salvaged: Totals,
+    pub destroyed: Totals,
+}
+
+impl SalvageTally {
+    pub fn add(&mut self, value: f64, mass: f64, destroyed: bool) {
+        if destroyed {
+            self.destroyed.add(value, mass)
+        } else {
+            self.salvaged.add(value, mass)
+        }
+    }
+
+    /// Add everything in `other` in.
+    pub fn merge(&mut self, other: &SalvageTally) {
+        self.salvaged.merge(&other.salvaged);
+        self.destroyed.merge(&other.destroyed);
+    }
+}
+
+/// How a finished shift went, as the lamprey works it out from every event in it. Sent as a `shiftSummaryEvent` right
+/// after the shift's `EndShiftEvent`, so clients don't each have to add everything up themselves (and get it subtly
+/// different from each other).
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ShiftSummary {
+    pub started: DateTime<Utc>,
+    pub ended: DateTime<Utc>,
+    // wall clock seconds between started and ended
+    pub duration: f64,
+    // the last time tick's current time, i.e. how far into the shift the game thinks we got, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game_time: Option<f64>,
+    pub salvaged: Totals,
+    pub destroyed: Totals,
+    pub by_salvaged_by: BTreeMap<String, SalvageTally>,
+    pub by_category: BTreeMap<String, SalvageTally>,
+    // what the EndShiftEvent said, missing if it didn't say
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_cause: Option<ExitCause>,
+    // missing if the shift wasn't a RACE
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub race: Option<RaceSummary>,
+}
+
+/// What `SetRACEInfoEvent` said about the RACE, and how close to its maximums we got.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RaceSummary {
+    pub seed: i64,
+    pub version: i64,
+    #[serde(rename = "startDateUTC")]
+    pub start_date_utc: String,
+    pub max_total_value: i64,
+    pub max_salvage_mass: i64,
+    // salvaged value as a percentage of max_total_value, missing if the max is 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percent_of_max_total_value: Option<f64>,
+    // salvaged mass as a percentage of max_salvage_mass, missing if the max is 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percent_of_max_salvage_mass: Option<f64>,
+}
@@ -0,0 +1,132 @@
+// How fast a real lamprey gets events from the mock mod out to its proxy clients. The README promises at least 100
+// events per second per client with 5 clients; this should come out a few orders of magnitude past that.
+use std::{
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use async_tungstenite::{tokio::connect_async, tungstenite::Message};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures::prelude::*;
+
+const CLIENTS: usize = 5;
+const EVENTS: usize = 1000;
+const MOD_PORT: u16 = 42280;
+const LISTEN_PORT: u16 = 42281;
+
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+}
+
+fn lamprey(args: &[&str]) -> KillOnDrop {
+    KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_racers-ledger-lamprey"))
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("couldn't start the lamprey"),
+    )
+}
+
+/// A shift with `EVENTS` salvage log entries in it, as a mock mod script.
+fn write_script() -> std::path::PathBuf {
+    let script = std::env::temp_dir().join(format!("lamprey-bench-{}.jsonl", std::process::id()));
+    let mut lines =
+        vec![r#"{"type":"startShiftEvent","systemTime":"2021-07-04T12:00:00Z"}"#.to_string()];
+    for i in 0..EVENTS {
+        lines.push(format!(
+            r#"{{"type":"shiftSalvageLogEntry","objectName":"Thing {i}","mass":1.5,"categories":["Salvage"],"salvagedBy":"Furnace","value":100.0,"massBasedValue":false,"destroyed":false,"gameTime":{i}.0,"systemTime":"2021-07-04T12:00:00Z"}}"#
+        ));
+    }
+    lines.push(r#"{"type":"endShiftEvent","systemTime":"2021-07-04T12:00:00Z"}"#.to_string());
+    std::fs::write(&script, lines.join("\n")).unwrap();
+    script
+}
+
+/// One shift through a fresh lamprey to `CLIENTS` clients. Only counts the time from the first event showing up to
+/// the last client getting the last one, not starting everything up.
+async fn one_shift(script: &str) -> Duration {
+    let _lamprey = lamprey(&[
+        "connect",
+        &MOD_PORT.to_string(),
+        &LISTEN_PORT.to_string(),
+        "--max-reconnect-delay",
+        "1",
+        "--notime-tick",
+        "--client-queue-size",
+        "100000",
+    ]);
+    let url = format!("ws://127.0.0.1:{LISTEN_PORT}/api/v0/racers-ledger-proxy");
+    let mut proxies = vec![];
+    while proxies.len() < CLIENTS {
+        match connect_async(url.as_str()).await {
+            Ok((proxy, _)) => proxies.push(proxy),
+            Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+        }
+    }
+    let _mock_mod = lamprey(&[
+        "mock-mod",
+        &MOD_PORT.to_string(),
+        "--speed",
+        "instant",
+        "--script",
+        script,
+    ]);
+
+    let clients = proxies.into_iter().map(|mut proxy| async move {
+        let mut first = None;
+        let mut salvaged = 0;
+        while let Some(Ok(msg)) = proxy.next().await {
+            if let Message::Text(text) = msg {
+                // the type always comes first, and the welcomes mention every type so don't just look for the name
+                if text.starts_with(r#"{"type":"shiftSalvageLogEntry""#) {
+                    first.get_or_insert_with(Instant::now);
+                    salvaged += 1;
+                } else if text.starts_with(r#"{"type":"lampreyDroppedEventsEvent""#) {
+                    panic!("the lamprey dropped events, that's not what we're measuring");
+                } else if text.starts_with(r#"{"type":"endShiftEvent""#) {
+                    assert_eq!(salvaged, EVENTS);
+                    return (first.unwrap(), Instant::now());
+                }
+            }
+        }
+        panic!("proxy closed before the shift ended");
+    });
+    let times = future::join_all(clients).await;
+    let first = times.iter().map(|(first, _)| *first).min().unwrap();
+    let last = times.iter().map(|(_, last)| *last).max().unwrap();
+    last - first
+}
+
+fn fan_out(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let script = write_script();
+    let script_path = script.to_str().unwrap().to_string();
+    let mut group = c.benchmark_group("fan_out");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(40));
+    group.throughput(Throughput::Elements((EVENTS * CLIENTS) as u64));
+    group.bench_function(format!("{EVENTS}_events_to_{CLIENTS}_clients"), |b| {
+        b.iter_custom(|iters| {
+            runtime.block_on(async {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    total += tokio::time::timeout(Duration::from_secs(30), one_shift(&script_path))
+                        .await
+                        .expect("timed out waiting for the shift to finish");
+                }
+                total
+            })
+        })
+    });
+    group.finish();
+    std::fs::remove_file(&script).ok();
+}
+
+criterion_group!(benches, fan_out);
+criterion_main!(benches);
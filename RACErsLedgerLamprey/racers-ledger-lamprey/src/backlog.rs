@@ -0,0 +1,158 @@
+use std::{collections::VecDeque, convert::TryFrom, str::FromStr};
+
+use serde::Deserialize;
+
+use racers_ledger_datatypes::SalvageEvent;
+
+use super::envelope::SharedEnvelope;
+
+/// Where a (re)connecting proxy client wants to pick the stream up from.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub enum Since {
+    /// Everything we have for the current shift.
+    ShiftStart,
+    /// Everything after the event with this sequence number.
+    Seq(u64),
+}
+
+impl FromStr for Since {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "shift_start" {
+            return Ok(Since::ShiftStart);
+        }
+        s.parse()
+            .map(Since::Seq)
+            .map_err(|_| format!("since should be `shift_start` or a sequence number, not {s:?}"))
+    }
+}
+
+impl TryFrom<String> for Since {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Events since the last `StartShiftEvent`, each with the sequence number the lamprey gave it, so that clients that
+/// show up late (or reload, or notice they missed something) can catch up on the whole shift.
+#[derive(Debug)]
+pub struct ShiftBacklog {
+    /// Most events we'll hold on to. When we go over, the oldest ones go, except for the start of the shift and the
+    /// latest RACE info, which are too important to lose.
+    capacity: usize,
+    shift_start: Option<SharedEnvelope>,
+    race_info: Option<SharedEnvelope>,
+    events: VecDeque<SharedEnvelope>,
+}
+
+impl ShiftBacklog {
+    pub fn new(capacity: usize) -> Self {
+        ShiftBacklog {
+            capacity,
+            shift_start: None,
+            race_info: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Remember an event.
+    pub fn push(&mut self, envelope: SharedEnvelope) {
+        match envelope.event {
+            SalvageEvent::StartShiftEvent { .. } => {
+                self.events.clear();
+                self.race_info = None;
+                self.shift_start = Some(envelope);
+            }
+            SalvageEvent::SetRACEInfoEvent { .. } => {
+                self.race_info = Some(envelope);
+            }
+            _ => {
+                self.events.push_back(envelope);
+                while self.events.len() > self.capacity {
+                    self.events.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Everything we have that a client asking for `since` hasn't seen yet, oldest first.
+    pub fn since(&self, since: Since) -> Vec<SharedEnvelope> {
+        let after = match since {
+            Since::ShiftStart => 0,
+            Since::Seq(seq) => seq,
+        };
+        self.between(after.saturating_add(1), u64::MAX)
+    }
+
+    /// Everything we still have from `first` to `last` (inclusive), oldest first. Anything that's not there anymore
+    /// (from before this shift, or dropped because the shift was too long) is just left out.
+    pub fn between(&self, first: u64, last: u64) -> Vec<SharedEnvelope> {
+        let mut envelopes: Vec<SharedEnvelope> = self
+            .shift_start
+            .iter()
+            .chain(self.race_info.iter())
+            .chain(self.events.iter())
+            .filter(|envelope| (first..=last).contains(&envelope.seq))
+            .cloned()
+            .collect();
+        envelopes.sort_by_key(|envelope| envelope.seq);
+        envelopes
+    }
+}
+
+#[test]
+fn test_since_parsing() {
+    assert_eq!("shift_start".parse(), Ok(Since::ShiftStart));
+    assert_eq!("42".parse(), Ok(Since::Seq(42)));
+    assert!("yesterday".parse::<Since>().is_err());
+}
+
+#[test]
+fn test_backlog_keeps_current_shift() {
+    let now = chrono::Utc::now();
+    let tick = |current_time| SalvageEvent::TimeTickEvent {
+        current_time,
+        max_time: 900.0,
+        system_time: now,
+    };
+    let mut next_seq = 0;
+    let mut envelope = |event| {
+        next_seq += 1;
+        SharedEnvelope::new(super::envelope::Envelope {
+            seq: next_seq,
+            received_time: now,
+            event,
+        })
+    };
+    let mut backlog = ShiftBacklog::new(3);
+    backlog.push(envelope(tick(1.0)));
+    backlog.push(envelope(SalvageEvent::StartShiftEvent { system_time: now }));
+    backlog.push(envelope(SalvageEvent::SetRACEInfoEvent {
+        seed: 1,
+        version: 2,
+        start_date_utc: "".into(),
+        max_total_value: 3,
+        max_salvage_mass: 4,
+        system_time: now,
+    }));
+    for current_time in 2..8 {
+        backlog.push(envelope(tick(current_time as f64)));
+    }
+
+    let seqs = |envelopes: Vec<SharedEnvelope>| {
+        envelopes
+            .into_iter()
+            .map(|envelope| envelope.seq)
+            .collect::<Vec<_>>()
+    };
+    // the tick before the shift is gone, and only the last 3 ticks fit, but the start and RACE info stick around
+    assert_eq!(seqs(backlog.since(Since::ShiftStart)), vec![2, 3, 7, 8, 9]);
+    assert_eq!(seqs(backlog.since(Since::Seq(7))), vec![8, 9]);
+    assert_eq!(seqs(backlog.since(Since::Seq(9))), Vec::<u64>::new());
+    // 4 to 6 were dropped, so a resend of 3 to 8 can only do so much
+    assert_eq!(seqs(backlog.between(3, 8)), vec![3, 7, 8]);
+}
@@ -0,0 +1,378 @@
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use serde::Deserialize;
+use tokio::sync::Notify;
+
+use racers_ledger_datatypes::SalvageEvent;
+
+#[cfg(test)]
+use super::envelope::{Envelope, SharedEnvelope};
+use super::ProxyMessage;
+
+/// Close code for clients we gave up on, "try again later".
+pub const TOO_SLOW_CLOSE_CODE: u16 = 1013;
+
+/// What to do when a proxy client falls so far behind that its queue is full.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub enum SlowClientPolicy {
+    /// Drop the oldest event it hasn't gotten yet.
+    DropOldest,
+    /// Drop `TimeTickEvent`s and the `RacePaceEvent`s and `GhostDeltaEvent`s that come with them (there's another
+    /// one coming in a second anyway), then the oldest events if there are no ticks left to drop.
+    DropTimeTicks,
+    /// Hang up on it with `TOO_SLOW_CLOSE_CODE`.
+    Disconnect,
+}
+
+impl FromStr for SlowClientPolicy {
+    type Err = String;
+
+    /// Accepts `drop-oldest`, `drop-time-ticks` or `disconnect`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "drop-oldest" => Ok(SlowClientPolicy::DropOldest),
+            "drop-time-ticks" => Ok(SlowClientPolicy::DropTimeTicks),
+            "disconnect" => Ok(SlowClientPolicy::Disconnect),
+            _ => Err(format!(
+                "{s:?} isn't `drop-oldest`, `drop-time-ticks` or `disconnect`"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for SlowClientPolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// How big proxy clients' queues are and what happens when they fill up.
+#[derive(Debug, Clone, Copy)]
+pub struct QueueOptions {
+    pub capacity: usize,
+    pub policy: SlowClientPolicy,
+}
+
+/// The proxy client's receiving side is gone, nobody's listening anymore.
+#[derive(Debug)]
+pub struct Disconnected;
+
+/// One queued message, and whether it counts towards the capacity. Catching up on the backlog and resends don't,
+/// since the backlog is bounded already and they'd just get thrown away otherwise.
+#[derive(Debug)]
+struct Queued {
+    message: ProxyMessage,
+    live: bool,
+}
+
+#[derive(Debug)]
+struct Queue {
+    messages: VecDeque<Queued>,
+    /// How many of `messages` are live.
+    live: usize,
+    /// Dropped since we last told the client about it.
+    unreported_dropped: u64,
+    dropped: u64,
+    /// We hung up on the client for being too slow, so don't bother queueing anything else.
+    disconnected: bool,
+    sender_gone: bool,
+    receiver_gone: bool,
+}
+
+impl Queue {
+    /// Make room for one more live message according to `policy`. Returns false if the incoming message should be
+    /// dropped instead.
+    fn make_room(&mut self, incoming: &ProxyMessage, policy: SlowClientPolicy) -> bool {
+        let is_tick = |message: &ProxyMessage| {
+            matches!(
+                message,
+                ProxyMessage::Event(envelope)
+                    if matches!(
+                        envelope.event,
+                        SalvageEvent::TimeTickEvent { .. }
+                            | SalvageEvent::RacePaceEvent { .. }
+                            | SalvageEvent::GhostDeltaEvent { .. }
+                    )
+            )
+        };
+        let victim = match policy {
+            SlowClientPolicy::Disconnect => {
+                // everything live it was waiting on plus the incoming one, same as the other policies count; what it
+                // was catching up on or the welcome never counted
+                let dropped = self.live as u64 + 1;
+                self.messages.clear();
+                self.live = 0;
+                self.unreported_dropped += dropped;
+                self.dropped += dropped;
+                self.disconnected = true;
+                self.messages.push_back(Queued {
+                    message: ProxyMessage::Close {
+                        code: TOO_SLOW_CLOSE_CODE,
+                        reason: "too slow, fell too far behind".to_string(),
+                    },
+                    live: false,
+                });
+                return false;
+            }
+            SlowClientPolicy::DropTimeTicks if is_tick(incoming) => None,
+            SlowClientPolicy::DropTimeTicks => self
+                .messages
+                .iter()
+                .position(|queued| queued.live && is_tick(&queued.message))
+                .or_else(|| self.messages.iter().position(|queued| queued.live)),
+            SlowClientPolicy::DropOldest => self.messages.iter().position(|queued| queued.live),
+        };
+        self.unreported_dropped += 1;
+        self.dropped += 1;
+        match victim {
+            Some(victim) => {
+                self.messages.remove(victim);
+                self.live -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Where a proxy client's messages wait until its connection gets around to sending them. Holds at most `capacity`
+/// live events; what happens past that is up to the `SlowClientPolicy`.
+pub fn channel(options: QueueOptions) -> (ClientSender, ClientReceiver) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue {
+            messages: VecDeque::new(),
+            live: 0,
+            unreported_dropped: 0,
+            dropped: 0,
+            disconnected: false,
+            sender_gone: false,
+            receiver_gone: false,
+        }),
+        notify: Notify::new(),
+        capacity: options.capacity.max(1),
+        policy: options.policy,
+    });
+    (
+        ClientSender {
+            shared: shared.clone(),
+        },
+        ClientReceiver { shared },
+    )
+}
+
+#[derive(Debug)]
+struct Shared {
+    queue: Mutex<Queue>,
+    notify: Notify,
+    capacity: usize,
+    policy: SlowClientPolicy,
+}
+
+impl Shared {
+    fn queue(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().expect("client queue lock poisoned")
+    }
+}
+
+/// The lamprey's end of a proxy client's queue.
+#[derive(Debug)]
+pub struct ClientSender {
+    shared: Arc<Shared>,
+}
+
+impl ClientSender {
+    /// Queue a message. Live events count towards the capacity, anything else (welcomes, close frames) always fits.
+    pub fn send(&self, message: ProxyMessage) -> Result<(), Disconnected> {
+        let live = matches!(message, ProxyMessage::Event(_));
+        self.push(message, live)
+    }
+
+    /// Queue an event the client asked to catch up on, which doesn't count towards the capacity.
+    pub fn send_catch_up(&self, message: ProxyMessage) -> Result<(), Disconnected> {
+        self.push(message, false)
+    }
+
+    fn push(&self, message: ProxyMessage, live: bool) -> Result<(), Disconnected> {
+        let mut queue = self.shared.queue();
+        if queue.receiver_gone {
+            return Err(Disconnected);
+        }
+        if queue.disconnected {
+            return Ok(());
+        }
+        if live
+            && queue.live >= self.shared.capacity
+            && !queue.make_room(&message, self.shared.policy)
+        {
+            self.shared.notify.notify_one();
+            return Ok(());
+        }
+        queue.live += live as usize;
+        queue.messages.push_back(Queued { message, live });
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+
+    /// How many messages are waiting for the client right now.
+    pub fn queued(&self) -> usize {
+        self.shared.queue().messages.len()
+    }
+
+    /// How many events this client has missed out on because it was too slow.
+    pub fn dropped(&self) -> u64 {
+        self.shared.queue().dropped
+    }
+}
+
+impl Drop for ClientSender {
+    fn drop(&mut self) {
+        self.shared.queue().sender_gone = true;
+        self.shared.notify.notify_one();
+    }
+}
+
+/// The proxy client connection's end of its queue.
+#[derive(Debug)]
+pub struct ClientReceiver {
+    shared: Arc<Shared>,
+}
+
+impl ClientReceiver {
+    /// The next message for the client, or `None` once the lamprey is done with it. If we had to drop anything since
+    /// last time, that comes first as a `LampreyDroppedEventsEvent`.
+    pub async fn recv(&mut self) -> Option<ProxyMessage> {
+        loop {
+            {
+                let mut queue = self.shared.queue();
+                if queue.unreported_dropped > 0 {
+                    let dropped = std::mem::take(&mut queue.unreported_dropped);
+                    return Some(ProxyMessage::Lamprey(
+                        SalvageEvent::LampreyDroppedEventsEvent {
+                            dropped,
+                            total_dropped: queue.dropped,
+                            system_time: Utc::now(),
+                        },
+                    ));
+                }
+                if let Some(queued) = queue.messages.pop_front() {
+                    queue.live -= queued.live as usize;
+                    return Some(queued.message);
+                }
+                if queue.sender_gone {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    /// Everything the client gets, as a stream.
+    pub fn into_stream(self) -> impl futures::Stream<Item = ProxyMessage> {
+        futures::stream::unfold(self, |mut receiver| async move {
+            receiver
+                .recv()
+                .await
+                .map(|proxy_message| (proxy_message, receiver))
+        })
+    }
+}
+
+impl Drop for ClientReceiver {
+    fn drop(&mut self) {
+        self.shared.queue().receiver_gone = true;
+    }
+}
+
+#[tokio::test]
+async fn test_slow_client_policies() {
+    fn tick(seq: u64) -> ProxyMessage {
+        ProxyMessage::Event(SharedEnvelope::new(Envelope {
+            seq,
+            received_time: Utc::now(),
+            event: SalvageEvent::TimeTickEvent {
+                current_time: seq as f64,
+                max_time: 900.0,
+                system_time: Utc::now(),
+            },
+        }))
+    }
+
+    fn salvage(seq: u64) -> ProxyMessage {
+        ProxyMessage::Event(SharedEnvelope::new(Envelope {
+            seq,
+            received_time: Utc::now(),
+            event: SalvageEvent::StartShiftEvent {
+                system_time: Utc::now(),
+            },
+        }))
+    }
+
+    async fn drain(receiver: &mut ClientReceiver) -> Vec<String> {
+        let mut drained = vec![];
+        while receiver.shared.queue().unreported_dropped > 0
+            || !receiver.shared.queue().messages.is_empty()
+        {
+            drained.push(match receiver.recv().await.unwrap() {
+                ProxyMessage::Event(envelope) => envelope.seq.to_string(),
+                ProxyMessage::Lamprey(SalvageEvent::LampreyDroppedEventsEvent {
+                    dropped, ..
+                }) => {
+                    format!("dropped {dropped}")
+                }
+                ProxyMessage::Close { code, .. } => format!("close {code}"),
+                proxy_message => panic!("didn't expect {:?}", proxy_message),
+            });
+        }
+        drained
+    }
+
+    let (sender, mut receiver) = channel(QueueOptions {
+        capacity: 3,
+        policy: SlowClientPolicy::DropOldest,
+    });
+    for seq in 1..=5 {
+        sender.send(salvage(seq)).unwrap();
+    }
+    assert_eq!(drain(&mut receiver).await, ["dropped 2", "3", "4", "5"]);
+
+    let (sender, mut receiver) = channel(QueueOptions {
+        capacity: 3,
+        policy: SlowClientPolicy::DropTimeTicks,
+    });
+    sender.send(salvage(1)).unwrap();
+    sender.send(tick(2)).unwrap();
+    sender.send(salvage(3)).unwrap();
+    sender.send(salvage(4)).unwrap();
+    sender.send(tick(5)).unwrap();
+    sender.send(salvage(6)).unwrap();
+    // catching up doesn't count, and doesn't get dropped
+    sender.send_catch_up(tick(7)).unwrap();
+    assert_eq!(
+        drain(&mut receiver).await,
+        ["dropped 3", "3", "4", "6", "7"]
+    );
+    assert_eq!(sender.dropped(), 3);
+
+    let (sender, mut receiver) = channel(QueueOptions {
+        capacity: 2,
+        policy: SlowClientPolicy::Disconnect,
+    });
+    sender.send_catch_up(salvage(1)).unwrap();
+    for seq in 2..=4 {
+        sender.send(salvage(seq)).unwrap();
+    }
+    sender.send(salvage(5)).unwrap();
+    assert_eq!(drain(&mut receiver).await, ["dropped 3", "close 1013"]);
+    assert_eq!(sender.dropped(), 3);
+    drop(receiver);
+    assert!(sender.send(salvage(6)).is_err());
+}
@@ -0,0 +1,314 @@
+use std::{
+    net::IpAddr,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+
+use clap::{parser::ValueSource, ArgMatches};
+use serde::Deserialize;
+
+use super::client_queue::SlowClientPolicy;
+use super::{Command, LogFormat, Opts, ServeCommand};
+
+/// Everything `--config` can set, which is (almost) everything the command line can. Anything left out of the file
+/// falls back to the command line defaults, and anything given on the command line beats the file.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub log: LogConfig,
+    pub upstream: UpstreamConfig,
+    pub listen: ListenConfig,
+    pub console: ConsoleConfig,
+    pub archive: ArchiveConfig,
+    pub database: DatabaseConfig,
+    pub retention: RetentionConfig,
+    pub clients: ClientsConfig,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: Option<LogFormat>,
+    /// Same as giving -v this many times.
+    pub verbose: Option<u8>,
+    pub colorize: Option<bool>,
+}
+
+/// Where the mod is, for `connect`.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub path: Option<String>,
+    /// In seconds.
+    pub max_reconnect_delay: Option<u64>,
+    pub persist: Option<bool>,
+}
+
+/// Where to serve the API.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenConfig {
+    pub address: Option<IpAddr>,
+    pub port: Option<u16>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsoleConfig {
+    pub enabled: Option<bool>,
+    pub time_ticks: Option<bool>,
+    pub skip_types: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    pub dir: Option<PathBuf>,
+    pub skip_types: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub path: Option<PathBuf>,
+}
+
+/// How long the archive and the database keep things, in days. Forever if not set.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub archive_days: Option<NonZeroU32>,
+    pub database_days: Option<NonZeroU32>,
+}
+
+/// Proxy clients, and how much the lamprey keeps around for them.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientsConfig {
+    pub backlog_size: Option<usize>,
+    pub queue_size: Option<usize>,
+    pub slow_client_policy: Option<SlowClientPolicy>,
+}
+
+impl Config {
+    /// Read a config file. Relative paths in it are relative to the file, not to wherever we were started from, so a
+    /// checked in config works from anywhere.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut config: Config = toml::from_str(&text).map_err(|e| e.to_string())?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        for relative in config
+            .archive
+            .dir
+            .iter_mut()
+            .chain(config.database.path.iter_mut())
+        {
+            *relative = base.join(&*relative);
+        }
+        Ok(config)
+    }
+
+    /// Fill in `opts` from the file, except for whatever `matches` says was given on the command line.
+    pub fn apply(self, opts: &mut Opts, matches: &ArgMatches) {
+        // global options end up in the subcommand's matches too, wherever they were given
+        let matches = matches.subcommand().map_or(matches, |(_, matches)| matches);
+        let from_file = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+        fn set<T>(field: &mut T, value: Option<T>, from_file: bool) {
+            if let (Some(value), true) = (value, from_file) {
+                *field = value;
+            }
+        }
+
+        set(
+            &mut opts.log_format,
+            self.log.format,
+            from_file("log_format"),
+        );
+        set(&mut opts.verbose, self.log.verbose, from_file("verbose"));
+        set(
+            &mut opts.nocolorize,
+            self.log.colorize.map(|colorize| !colorize),
+            from_file("nocolorize"),
+        );
+        set(
+            &mut opts.listen_address,
+            self.listen.address,
+            from_file("listen_address"),
+        );
+        set(
+            &mut opts.noconsole,
+            self.console.enabled.map(|enabled| !enabled),
+            from_file("noconsole"),
+        );
+        set(
+            &mut opts.notime_tick,
+            self.console.time_ticks.map(|time_ticks| !time_ticks),
+            from_file("notime_tick"),
+        );
+        set(
+            &mut opts.console_skip_types,
+            self.console.skip_types,
+            from_file("console_skip_types"),
+        );
+        set(
+            &mut opts.archive_dir,
+            self.archive.dir.map(Some),
+            from_file("archive_dir"),
+        );
+        set(
+            &mut opts.archive_skip_types,
+            self.archive.skip_types,
+            from_file("archive_skip_types"),
+        );
+        set(
+            &mut opts.database,
+            self.database.path.map(Some),
+            from_file("database"),
+        );
+        set(
+            &mut opts.archive_retention_days,
+            self.retention.archive_days.map(Some),
+            from_file("archive_retention_days"),
+        );
+        set(
+            &mut opts.database_retention_days,
+            self.retention.database_days.map(Some),
+            from_file("database_retention_days"),
+        );
+        set(
+            &mut opts.backlog_size,
+            self.clients.backlog_size,
+            from_file("backlog_size"),
+        );
+        set(
+            &mut opts.client_queue_size,
+            self.clients.queue_size,
+            from_file("client_queue_size"),
+        );
+        set(
+            &mut opts.slow_client_policy,
+            self.clients.slow_client_policy,
+            from_file("slow_client_policy"),
+        );
+
+        match &mut opts.command {
+            Command::Serve(ServeCommand::Connect {
+                connect_port,
+                listen_port,
+                connect_host,
+                connect_path,
+                max_reconnect_delay,
+                persist,
+            }) => {
+                set(
+                    connect_port,
+                    self.upstream.port.map(Some),
+                    from_file("connect_port"),
+                );
+                set(
+                    listen_port,
+                    self.listen.port.map(Some),
+                    from_file("listen_port"),
+                );
+                set(connect_host, self.upstream.host, from_file("connect_host"));
+                set(connect_path, self.upstream.path, from_file("connect_path"));
+                set(
+                    max_reconnect_delay,
+                    self.upstream.max_reconnect_delay,
+                    from_file("max_reconnect_delay"),
+                );
+                set(persist, self.upstream.persist, from_file("persist"));
+            }
+            Command::Serve(ServeCommand::Replay { listen_port, .. }) => {
+                set(
+                    listen_port,
+                    self.listen.port.map(Some),
+                    from_file("listen_port"),
+                );
+            }
+            // the mock mod and importing have nothing worth sharing a config for
+            Command::MockMod { .. } | Command::Import { .. } | Command::Losses { .. } => {}
+        }
+    }
+}
+
+#[test]
+fn test_command_line_beats_config() {
+    use clap::{CommandFactory, FromArgMatches};
+
+    let config: Config = toml::from_str(
+        r#"
+        [log]
+        format = "pretty_and_all_spans"
+        verbose = 2
+
+        [upstream]
+        port = 32325
+        path = "/somewhere-else/"
+        persist = true
+
+        [listen]
+        address = "10.1.2.3"
+        port = 42069
+
+        [console]
+        time_ticks = false
+        skip_types = ["gameStateChangedEvent"]
+
+        [retention]
+        archive_days = 30
+
+        [clients]
+        queue_size = 10
+        slow_client_policy = "disconnect"
+        "#,
+    )
+    .unwrap();
+    let matches = Opts::command().get_matches_from([
+        "racers-ledger-lamprey",
+        // global options count before the subcommand and after it
+        "--client-queue-size",
+        "20",
+        "connect",
+        "--verbose",
+    ]);
+    let mut opts = Opts::from_arg_matches(&matches).unwrap();
+    config.apply(&mut opts, &matches);
+
+    assert_eq!(opts.log_format, LogFormat::PrettyAndAllSpans);
+    assert_eq!(opts.verbose, 1);
+    assert!(opts.notime_tick);
+    assert_eq!(opts.console_skip_types, ["gameStateChangedEvent"]);
+    assert_eq!(opts.client_queue_size, 20);
+    assert_eq!(opts.slow_client_policy, SlowClientPolicy::Disconnect);
+    assert_eq!(opts.listen_address.to_string(), "10.1.2.3");
+    assert_eq!(opts.archive_retention_days, NonZeroU32::new(30));
+    // and nobody said anything about these
+    assert_eq!(opts.backlog_size, 20000);
+    assert!(opts.archive_dir.is_none());
+    assert!(opts.database_retention_days.is_none());
+    match opts.command {
+        Command::Serve(ServeCommand::Connect {
+            connect_port,
+            listen_port,
+            connect_host,
+            connect_path,
+            persist,
+            ..
+        }) => {
+            assert_eq!(connect_port, Some(32325));
+            assert_eq!(listen_port, Some(42069));
+            assert_eq!(connect_host, "localhost");
+            assert_eq!(connect_path, "/somewhere-else/");
+            assert!(persist);
+        }
+        _ => unreachable!(),
+    }
+
+    // typos shouldn't just get ignored
+    assert!(toml::from_str::<Config>("[upstream]\nprot = 1").is_err());
+    // and neither should keeping things for no time at all
+    assert!(toml::from_str::<Config>("[retention]\ndatabase_days = 0").is_err());
+}
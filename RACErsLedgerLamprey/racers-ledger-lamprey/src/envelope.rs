@@ -0,0 +1,461 @@
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex, OnceLock, Weak},
+};
+
+use chrono::{DateTime, Utc};
+use log::{debug, error};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use racers_ledger_datatypes::SalvageEvent;
+
+use super::metrics::Metrics;
+use super::shift::ShiftAggregate;
+use super::sinks::salvage_event_json;
+
+/// A ledger event as it goes through the lamprey, stamped with where it is in the stream and when we got it.
+///
+/// Sequence numbers start at 1 with the first event the lamprey ever hears about and go up by one every event, so a
+/// hole in them means something got missed. 0 is never a real event.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub seq: u64,
+    pub received_time: DateTime<Utc>,
+    pub event: SalvageEvent,
+}
+
+/// An `Envelope` on its way out to proxy clients, which turns itself into JSON (either way clients can ask for it) at
+/// most once, however many clients it goes to. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct SharedEnvelope(Arc<EncodedEnvelope>);
+
+#[derive(Debug)]
+struct EncodedEnvelope {
+    envelope: Envelope,
+    event_json: OnceLock<Arc<str>>,
+    envelope_json: OnceLock<Arc<str>>,
+}
+
+impl SharedEnvelope {
+    pub fn new(envelope: Envelope) -> Self {
+        SharedEnvelope(Arc::new(EncodedEnvelope {
+            envelope,
+            event_json: OnceLock::new(),
+            envelope_json: OnceLock::new(),
+        }))
+    }
+
+    /// The JSON for a proxy client: just the event, or the whole envelope if it asked for that.
+    pub fn json(&self, wants_envelope: bool) -> Arc<str> {
+        let encoded = &self.0;
+        let event_json = || Arc::from(salvage_event_json(&encoded.envelope.event));
+        if !wants_envelope {
+            return encoded.event_json.get_or_init(event_json).clone();
+        }
+        encoded
+            .envelope_json
+            .get_or_init(|| match serde_json::to_string(&encoded.envelope) {
+                Ok(json) => Arc::from(json),
+                Err(e) => {
+                    error!(
+                        "somehow failed to serialize envelope {}: {e}",
+                        encoded.envelope.seq
+                    );
+                    event_json()
+                }
+            })
+            .clone()
+    }
+}
+
+impl Deref for SharedEnvelope {
+    type Target = Envelope;
+
+    fn deref(&self) -> &Envelope {
+        &self.0.envelope
+    }
+}
+
+/// The sending side of the ledger events broadcast channel, which hands out the sequence numbers and adds the
+/// events the lamprey derives from the stream itself (`racePaceEvent` and `shiftSummaryEvent`; sinks can derive their
+/// own with `send_derived`, like the ghost sink does).
+///
+/// Cheap to clone, every clone shares the same sequence.
+#[derive(Debug, Clone)]
+pub struct LedgerEventsSender {
+    sender: broadcast::Sender<Envelope>,
+    stream: Arc<Mutex<Stream>>,
+}
+
+#[derive(Debug)]
+struct Stream {
+    next_seq: u64,
+    /// The shift we're in, or the last one if we're between shifts. The only running totals the lamprey keeps, so
+    /// what `/api/v0/shift/current` says and what the `shiftSummaryEvent` says can't disagree.
+    current_shift: Option<ShiftAggregate>,
+}
+
+impl LedgerEventsSender {
+    pub fn new(capacity: usize) -> Self {
+        LedgerEventsSender {
+            sender: broadcast::channel(capacity).0,
+            stream: Arc::new(Mutex::new(Stream {
+                next_seq: 1,
+                current_shift: None,
+            })),
+        }
+    }
+
+    /// Stamp an event from the mod (or an archive being replayed) and send it to every sink, followed by anything
+    /// derived from it. Returns the sequence number it got (0 if it got dropped).
+    pub fn send(&self, salvage_event: SalvageEvent) -> u64 {
+        match &salvage_event {
+            // we work these out ourselves, so one in a replayed archive would be a duplicate
+            SalvageEvent::RacePaceEvent { .. }
+            | SalvageEvent::GhostDeltaEvent { .. }
+            | SalvageEvent::GhostSplitEvent { .. }
+            | SalvageEvent::ShiftSummaryEvent { .. } => {
+                debug!(
+                    "dropping a {} we didn't derive ourselves",
+                    salvage_event.event_type()
+                );
+                0
+            }
+            // and these are about a lamprey that's long gone by the time its archive gets replayed, not this one
+            SalvageEvent::UpstreamConnectedEvent { .. }
+            | SalvageEvent::UpstreamDisconnectedEvent { .. }
+            | SalvageEvent::LampreyShutdownEvent { .. } => {
+                debug!(
+                    "dropping a {} some other lamprey sent",
+                    salvage_event.event_type()
+                );
+                0
+            }
+            _ => self.send_own(salvage_event),
+        }
+    }
+
+    /// Like `send`, but for events the lamprey sends itself about itself (connecting to the mod, losing it, shutting
+    /// down), which `send` drops.
+    pub fn send_own(&self, salvage_event: SalvageEvent) -> u64 {
+        // hold the lock while sending, so events go out in sequence number order even with more than one sender, and
+        // nothing can sneak in between an event and what's derived from it
+        let mut stream = self.stream.lock().expect("event stream lock poisoned");
+        match &salvage_event {
+            SalvageEvent::StartShiftEvent { system_time } => {
+                stream.current_shift = Some(ShiftAggregate::new(*system_time));
+            }
+            // if the mod went away mid-shift, we'll never know how it ended
+            SalvageEvent::UpstreamDisconnectedEvent { .. }
+                if stream
+                    .current_shift
+                    .as_ref()
+                    .is_some_and(|shift| shift.ended.is_none()) =>
+            {
+                stream.current_shift = None;
+            }
+            _ => {}
+        }
+        // nothing after a shift's end is part of it, it just stays around until the next one starts
+        let open_shift = stream
+            .current_shift
+            .as_mut()
+            .filter(|shift| shift.ended.is_none());
+        let derived = open_shift.and_then(|shift| {
+            shift.record(&salvage_event);
+            match &salvage_event {
+                SalvageEvent::TimeTickEvent { system_time, .. } => shift.race_pace(*system_time),
+                SalvageEvent::EndShiftEvent { system_time, .. } => {
+                    shift
+                        .summary()
+                        .map(|summary| SalvageEvent::ShiftSummaryEvent {
+                            summary: Box::new(summary),
+                            system_time: *system_time,
+                        })
+                }
+                _ => None,
+            }
+        });
+        let seq = self.send_locked(&mut stream, salvage_event);
+        if let Some(derived) = derived {
+            self.send_locked(&mut stream, derived);
+        }
+        seq
+    }
+
+    /// Send an event a sink worked out from the stream, as is. Returns the sequence number it got.
+    pub fn send_derived(&self, salvage_event: SalvageEvent) -> u64 {
+        let mut stream = self.stream.lock().expect("event stream lock poisoned");
+        self.send_locked(&mut stream, salvage_event)
+    }
+
+    /// The running totals for the current shift, for whoever wants to look at them without sending anything.
+    pub fn current_shift(&self) -> CurrentShift {
+        CurrentShift(self.stream.clone())
+    }
+
+    /// A sender that doesn't keep the channel open, for sinks that send events of their own: otherwise the sinks
+    /// would never run out of events to wait for, even after everything else has let go of its sender.
+    pub fn downgrade(&self) -> WeakLedgerEventsSender {
+        WeakLedgerEventsSender {
+            sender: self.sender.downgrade(),
+            stream: Arc::downgrade(&self.stream),
+        }
+    }
+
+    fn send_locked(&self, stream: &mut Stream, salvage_event: SalvageEvent) -> u64 {
+        let seq = stream.next_seq;
+        stream.next_seq += 1;
+        let envelope = Envelope {
+            seq,
+            received_time: Utc::now(),
+            event: salvage_event,
+        };
+        // if we ever make ALL of the sinks optional this can fail, which just means nobody is listening
+        if let Err(e) = self.sender.send(envelope) {
+            debug!("nobody is listening to the ledger events channel: {e}");
+        }
+        seq
+    }
+
+    /// A new receiver for the sink called `sink`, which gets everything sent from now on.
+    pub fn subscribe(&self, sink: &'static str, metrics: Metrics) -> LedgerEventsReceiver {
+        LedgerEventsReceiver {
+            receiver: self.sender.subscribe(),
+            sink,
+            metrics,
+            last_seq: None,
+        }
+    }
+}
+
+/// The running totals `LedgerEventsSender` keeps for the current shift (or the last one, between shifts). Doesn't keep
+/// the channel open. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct CurrentShift(Arc<Mutex<Stream>>);
+
+impl CurrentShift {
+    /// The totals as of the last event sent, `None` if we haven't seen a shift start (or the mod went away during it).
+    pub fn get(&self) -> Option<ShiftAggregate> {
+        self.0
+            .lock()
+            .expect("event stream lock poisoned")
+            .current_shift
+            .clone()
+    }
+}
+
+/// See `LedgerEventsSender::downgrade`.
+#[derive(Debug, Clone)]
+pub struct WeakLedgerEventsSender {
+    sender: broadcast::WeakSender<Envelope>,
+    stream: Weak<Mutex<Stream>>,
+}
+
+impl WeakLedgerEventsSender {
+    /// The real sender, unless every one of those is gone already (i.e. we're shutting down).
+    pub fn upgrade(&self) -> Option<LedgerEventsSender> {
+        Some(LedgerEventsSender {
+            sender: self.sender.upgrade()?,
+            stream: self.stream.upgrade()?,
+        })
+    }
+}
+
+/// A sink's end of the ledger events broadcast channel. Keeps track of sequence numbers so that when the sink can't
+/// keep up, it can say exactly what it missed.
+#[derive(Debug)]
+pub struct LedgerEventsReceiver {
+    receiver: broadcast::Receiver<Envelope>,
+    sink: &'static str,
+    metrics: Metrics,
+    last_seq: Option<u64>,
+}
+
+impl LedgerEventsReceiver {
+    /// The next event, or `None` once every sender is gone and there's nothing more coming.
+    pub async fn recv(&mut self) -> Option<Envelope> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(envelope) => {
+                    if let Some((first, last)) = self.gap_before(envelope.seq) {
+                        self.metrics.lagged(self.sink, last - first + 1);
+                        error!(
+                            "{} sink missed events {first} to {last} ({} of them) :(",
+                            self.sink,
+                            last - first + 1
+                        );
+                    }
+                    self.last_seq = Some(envelope.seq);
+                    return Some(envelope);
+                }
+                Err(RecvError::Lagged(lagged_messages)) => {
+                    // the next event we get tells us exactly which ones these were
+                    debug!("{} sink lagged by {lagged_messages} events", self.sink);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// The first and last sequence numbers we skipped over to get to `seq`, if any.
+    fn gap_before(&self, seq: u64) -> Option<(u64, u64)> {
+        let first = self.last_seq? + 1;
+        (seq > first).then_some((first, seq - 1))
+    }
+}
+
+#[tokio::test]
+async fn test_receiver_reports_exactly_what_it_missed() {
+    let now = Utc::now();
+    let sender = LedgerEventsSender::new(2);
+    let metrics = Metrics::default();
+    let mut receiver = sender.subscribe("test", metrics.clone());
+    let tick = |current_time| SalvageEvent::TimeTickEvent {
+        current_time,
+        max_time: 900.0,
+        system_time: now,
+    };
+    assert_eq!(sender.send(tick(1.0)), 1);
+    assert_eq!(receiver.recv().await.unwrap().seq, 1);
+    // only the last two fit in the channel, so 2 to 4 get dropped on the floor
+    for current_time in 2..=6 {
+        sender.send(tick(current_time as f64));
+    }
+    let envelope = receiver.recv().await.unwrap();
+    assert_eq!(envelope.seq, 5);
+    assert!(matches!(
+        envelope.event,
+        SalvageEvent::TimeTickEvent { current_time, .. } if current_time == 5.0
+    ));
+    assert_eq!(receiver.recv().await.unwrap().seq, 6);
+    assert!(metrics
+        .encode(0)
+        .contains(r#"racers_ledger_sink_lagged_messages_total{sink="test"} 3"#));
+    assert!(receiver.gap_before(7).is_none());
+    assert_eq!(receiver.gap_before(9), Some((7, 8)));
+}
+
+#[tokio::test]
+async fn test_shift_summary_follows_end_shift() {
+    let now = Utc::now();
+    let sender = LedgerEventsSender::new(16);
+    let mut receiver = sender.subscribe("test", Metrics::default());
+    sender.send(SalvageEvent::StartShiftEvent { system_time: now });
+    assert_eq!(
+        sender.send(SalvageEvent::EndShiftEvent {
+            exit_cause: None,
+            system_time: now
+        }),
+        2
+    );
+    let mut events = vec![];
+    for _ in 0..3 {
+        events.push(receiver.recv().await.unwrap());
+    }
+    assert_eq!(events[2].seq, 3);
+    let summary = match &events[2].event {
+        SalvageEvent::ShiftSummaryEvent { summary, .. } => summary.clone(),
+        other => panic!("expected a shift summary, got {:?}", other),
+    };
+    // and one left over in an archive we're replaying goes nowhere, we already have our own
+    assert_eq!(
+        sender.send(SalvageEvent::ShiftSummaryEvent {
+            summary: summary.clone(),
+            system_time: now,
+        }),
+        0
+    );
+    // an end without a start (i.e. we connected mid-shift) has nothing to summarize
+    assert_eq!(
+        sender.send(SalvageEvent::EndShiftEvent {
+            exit_cause: None,
+            system_time: now
+        }),
+        4
+    );
+    // the ended shift is still what /api/v0/shift/current shows, and the summary came from the very same totals
+    let shift_totals = sender.current_shift();
+    let ended = shift_totals.get().unwrap();
+    assert_eq!(ended.ended, Some(now));
+    assert_eq!(ended.summary().map(Box::new), Some(summary));
+    sender.send(SalvageEvent::StartShiftEvent { system_time: now });
+    assert_eq!(receiver.recv().await.unwrap().seq, 4);
+    assert_eq!(receiver.recv().await.unwrap().seq, 5);
+    assert!(shift_totals.get().unwrap().ended.is_none());
+    // and a shift the mod went away in the middle of is no shift at all
+    sender.send_own(SalvageEvent::UpstreamDisconnectedEvent {
+        reason: "connection reset".into(),
+        close_code: None,
+        system_time: now,
+    });
+    assert!(shift_totals.get().is_none());
+}
+
+#[tokio::test]
+async fn test_replayed_lamprey_events_go_nowhere() {
+    use racers_ledger_datatypes::ShutdownCause;
+
+    let now = Utc::now();
+    let sender = LedgerEventsSender::new(16);
+    let mut receiver = sender.subscribe("test", Metrics::default());
+    let disconnected = || SalvageEvent::UpstreamDisconnectedEvent {
+        reason: "game closed! (probably)".into(),
+        close_code: Some(1000),
+        system_time: now,
+    };
+    sender.send(SalvageEvent::StartShiftEvent { system_time: now });
+    // what an archive written by an earlier lamprey has in it, which shouldn't reach anyone or end our shift early
+    assert_eq!(sender.send(disconnected()), 0);
+    assert_eq!(
+        sender.send(SalvageEvent::UpstreamConnectedEvent { system_time: now }),
+        0
+    );
+    assert_eq!(
+        sender.send(SalvageEvent::LampreyShutdownEvent {
+            cause: ShutdownCause::GameExited,
+            code: 1000,
+            reason: "game closed! (probably)".into(),
+            system_time: now,
+        }),
+        0
+    );
+    sender.send(SalvageEvent::EndShiftEvent {
+        exit_cause: None,
+        system_time: now,
+    });
+    assert_eq!(receiver.recv().await.unwrap().seq, 1);
+    assert!(matches!(
+        receiver.recv().await.unwrap().event,
+        SalvageEvent::EndShiftEvent { .. }
+    ));
+    assert!(matches!(
+        receiver.recv().await.unwrap().event,
+        SalvageEvent::ShiftSummaryEvent { .. }
+    ));
+    // our own still go through
+    assert_eq!(sender.send_own(disconnected()), 4);
+}
+
+#[test]
+fn test_shared_envelope_serializes_once() {
+    let envelope = SharedEnvelope::new(Envelope {
+        seq: 7,
+        received_time: Utc::now(),
+        event: SalvageEvent::StartShiftEvent {
+            system_time: Utc::now(),
+        },
+    });
+    let clone = envelope.clone();
+    assert!(Arc::ptr_eq(&envelope.json(false), &clone.json(false)));
+    assert!(Arc::ptr_eq(&envelope.json(true), &clone.json(true)));
+    let json: serde_json::Value = serde_json::from_str(&envelope.json(true)).unwrap();
+    assert_eq!(json["seq"], 7);
+    assert_eq!(json["event"]["type"], "startShiftEvent");
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&envelope.json(false)).unwrap(),
+        json["event"]
+    );
+}
@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, HashSet};
+
+use racers_ledger_datatypes::SalvageEvent;
+
+use super::storage::StoredShiftWithEntries;
+
+/// A recorded run of a RACE, boiled down to what we race against: how much it had salvaged when, and when it got
+/// done with each category.
+#[derive(Debug, Clone)]
+pub struct Ghost {
+    pub shift_id: i64,
+    /// Game time and salvaged value so far, after every (not destroyed) salvage, in order.
+    value_by_time: Vec<(f64, f64)>,
+    /// Every category the ghost salvaged anything worth something in: its value in that category by the end of the
+    /// shift, when it got there, and its total salvaged value at that point.
+    categories: BTreeMap<String, CategorySplit>,
+}
+
+#[derive(Debug, Clone)]
+struct CategorySplit {
+    value: f64,
+    game_time: f64,
+    total_value: f64,
+}
+
+impl Ghost {
+    pub fn new(shift: &StoredShiftWithEntries) -> Self {
+        let mut value_by_time = vec![];
+        let mut categories: BTreeMap<String, CategorySplit> = BTreeMap::new();
+        let mut total_value = 0.0;
+        // entries come out of the database in game time order already
+        for salvage_event in &shift.entries {
+            if let SalvageEvent::ShiftSalvageLogEntry {
+                categories: entry_categories,
+                value,
+                destroyed: false,
+                game_time,
+                ..
+            } = salvage_event
+            {
+                let game_time = f64::from(*game_time);
+                total_value += value;
+                value_by_time.push((game_time, total_value));
+                for category in entry_categories {
+                    let split = categories.entry(category.clone()).or_insert(CategorySplit {
+                        value: 0.0,
+                        game_time,
+                        total_value,
+                    });
+                    split.value += value;
+                    split.game_time = game_time;
+                    split.total_value = total_value;
+                }
+            }
+        }
+        categories.retain(|_, split| split.value > 0.0);
+        Ghost {
+            shift_id: shift.shift.id,
+            value_by_time,
+            categories,
+        }
+    }
+
+    /// What the ghost had salvaged by `game_time`.
+    pub fn value_at(&self, game_time: f64) -> f64 {
+        match self
+            .value_by_time
+            .partition_point(|(salvaged_at, _)| *salvaged_at <= game_time)
+        {
+            0 => 0.0,
+            salvaged => self.value_by_time[salvaged - 1].1,
+        }
+    }
+
+    /// What the ghost had salvaged by the end of its shift.
+    pub fn final_value(&self) -> f64 {
+        self.value_by_time.last().map_or(0.0, |(_, value)| *value)
+    }
+}
+
+/// The current run of a RACE against a `Ghost`, turning its events into `GhostDeltaEvent`s and `GhostSplitEvent`s.
+#[derive(Debug)]
+pub struct GhostRace {
+    ghost: Ghost,
+    value: f64,
+    by_category: BTreeMap<String, f64>,
+    /// Categories we've caught up with the ghost on already, so they only get one split each.
+    split: HashSet<String>,
+}
+
+impl GhostRace {
+    pub fn new(ghost: Ghost) -> Self {
+        GhostRace {
+            ghost,
+            value: 0.0,
+            by_category: BTreeMap::new(),
+            split: HashSet::new(),
+        }
+    }
+
+    /// Fold one event of the current run in, returning how we're doing against the ghost because of it: a delta for
+    /// every time tick, a split for every category we just caught up with the ghost on.
+    pub fn record(&mut self, salvage_event: &SalvageEvent) -> Vec<SalvageEvent> {
+        match salvage_event {
+            SalvageEvent::TimeTickEvent {
+                current_time,
+                system_time,
+                ..
+            } => {
+                let ghost_value = self.ghost.value_at(*current_time);
+                vec![SalvageEvent::GhostDeltaEvent {
+                    ghost_shift_id: self.ghost.shift_id,
+                    game_time: *current_time,
+                    value: self.value,
+                    ghost_value,
+                    value_delta: self.value - ghost_value,
+                    ghost_final_value: self.ghost.final_value(),
+                    system_time: *system_time,
+                }]
+            }
+            SalvageEvent::ShiftSalvageLogEntry {
+                categories,
+                value,
+                destroyed: false,
+                game_time,
+                system_time,
+                ..
+            } => {
+                self.value += value;
+                let mut splits = vec![];
+                for category in categories {
+                    let category_value = self.by_category.entry(category.clone()).or_default();
+                    *category_value += value;
+                    let Some(ghost_split) = self.ghost.categories.get(category) else {
+                        continue;
+                    };
+                    if *category_value < ghost_split.value || !self.split.insert(category.clone()) {
+                        continue;
+                    }
+                    let game_time = f64::from(*game_time);
+                    splits.push(SalvageEvent::GhostSplitEvent {
+                        ghost_shift_id: self.ghost.shift_id,
+                        category: category.clone(),
+                        game_time,
+                        ghost_game_time: ghost_split.game_time,
+                        time_delta: game_time - ghost_split.game_time,
+                        value_delta: self.value - ghost_split.total_value,
+                        system_time: *system_time,
+                    });
+                }
+                splits
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[test]
+fn test_ghost_race() {
+    use chrono::Utc;
+
+    use super::shift::SalvageTally;
+    use super::storage::StoredShift;
+
+    let now = Utc::now();
+    let entry = |category: &str, value, destroyed, game_time| SalvageEvent::ShiftSalvageLogEntry {
+        object_name: "Thing".into(),
+        mass: 1.0,
+        categories: vec![category.into()],
+        salvaged_by: "Furnace".into(),
+        value,
+        mass_based_value: false,
+        destroyed,
+        game_time,
+        system_time: now,
+    };
+    let tick = |current_time| SalvageEvent::TimeTickEvent {
+        current_time,
+        max_time: 900.0,
+        system_time: now,
+    };
+    let ghost = Ghost::new(&StoredShiftWithEntries {
+        shift: StoredShift {
+            id: 7,
+            started: now,
+            ended: Some(now),
+            exit_cause: None,
+            race_info: None,
+            totals: SalvageTally::default(),
+        },
+        entries: vec![
+            entry("Ferrous", 100.0, false, 10.0),
+            entry("Reactor", 500.0, true, 15.0),
+            entry("Reactor", 300.0, false, 20.0),
+            entry("Ferrous", 50.0, false, 30.0),
+        ],
+    });
+    assert_eq!(ghost.value_at(5.0), 0.0);
+    assert_eq!(ghost.value_at(20.0), 400.0);
+    assert_eq!(ghost.final_value(), 450.0);
+
+    let mut race = GhostRace::new(ghost);
+    assert_eq!(race.record(&entry("Reactor", 400.0, false, 12.0)).len(), 1);
+    match race.record(&tick(25.0)).as_slice() {
+        [SalvageEvent::GhostDeltaEvent {
+            ghost_shift_id: 7,
+            value,
+            ghost_value,
+            value_delta,
+            ..
+        }] => {
+            assert_eq!(*value, 400.0);
+            assert_eq!(*ghost_value, 400.0);
+            assert_eq!(*value_delta, 0.0);
+        }
+        other => panic!("expected a delta, got {:?}", other),
+    }
+    // destroyed doesn't count, and neither does only getting partway there
+    assert!(race
+        .record(&entry("Ferrous", 1000.0, true, 26.0))
+        .is_empty());
+    assert!(race
+        .record(&entry("Ferrous", 100.0, false, 27.0))
+        .is_empty());
+    match race.record(&entry("Ferrous", 50.0, false, 28.0)).as_slice() {
+        [SalvageEvent::GhostSplitEvent {
+            category,
+            time_delta,
+            value_delta,
+            ..
+        }] => {
+            assert_eq!(category, "Ferrous");
+            // the ghost finished off Ferrous at 30s, with 450 salvaged
+            assert_eq!(*time_delta, -2.0);
+            assert_eq!(*value_delta, 100.0);
+        }
+        other => panic!("expected a split, got {:?}", other),
+    }
+    // one split per category
+    assert!(race.record(&entry("Ferrous", 50.0, false, 29.0)).is_empty());
+}
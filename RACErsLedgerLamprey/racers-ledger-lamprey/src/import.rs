@@ -0,0 +1,241 @@
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use log::{error, info};
+
+use racers_ledger_datatypes::ledger_files::ImportedShift;
+
+use super::sinks::{archive_file_name, salvage_event_json};
+use super::storage::Database;
+
+/// Every `_ledger.csv` in `paths`, which can be files or folders (not searched recursively, the mod doesn't nest them).
+fn find_ledgers(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut ledgers = vec![];
+    for path in paths {
+        if !path.is_dir() {
+            ledgers.push(path.clone());
+            continue;
+        }
+        match std::fs::read_dir(path) {
+            Ok(entries) => {
+                let mut found: Vec<PathBuf> = entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| {
+                        path.file_name()
+                            .and_then(|file_name| file_name.to_str())
+                            .is_some_and(|file_name| file_name.ends_with("_ledger.csv"))
+                    })
+                    .collect();
+                found.sort();
+                ledgers.extend(found);
+            }
+            Err(e) => error!("couldn't look through {path:?}: {e}"),
+        }
+    }
+    ledgers
+}
+
+/// Write a shift's events into the archive, the same way the archive sink would have. Returns false if it was there already.
+fn archive(shift: &ImportedShift, archive_dir: &Path) -> std::io::Result<bool> {
+    let race_version = shift
+        .summary
+        .as_ref()
+        .and_then(|summary| summary.race_info.as_ref())
+        .map(|race_info| race_info.version)
+        .or(shift.race_version);
+    std::fs::create_dir_all(archive_dir)?;
+    let path = archive_dir.join(archive_file_name(shift.started, race_version));
+    let file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let mut writer = BufWriter::new(file);
+    for salvage_event in shift.events() {
+        writeln!(writer, "{}", salvage_event_json(&salvage_event))?;
+    }
+    writer.flush()?;
+    info!("archived {:?} to {path:?}", shift.ledger_path);
+    Ok(true)
+}
+
+/// Store a shift's events in the database, the same way the database sink would have. Returns false if it was there
+/// already, whether from an earlier import or because the lamprey recorded it live.
+fn store(shift: &ImportedShift, database: &Database) -> rusqlite::Result<bool> {
+    database.record_shift(shift.started, &shift.events())
+}
+
+/// Tell `out` how importing a ledger went. Not being able to say so doesn't make it any less imported.
+fn report(out: &mut impl Write, status: std::fmt::Arguments<'_>) {
+    if let Err(e) = writeln!(out, "{status}") {
+        error!("couldn't report on the import: {e}");
+    }
+}
+
+/// Import the mod's `_ledger.csv` (and `_summary.txt`) files into the database and/or the event archive. If neither was
+/// asked for, the events go to `out` (i.e. stdout) as JSON Lines instead, ready for `replay`; otherwise `out` hears how
+/// each ledger went.
+///
+/// Does blocking IO, so call it from `spawn_blocking`. Returns how many ledgers couldn't be imported.
+pub fn import(
+    paths: &[PathBuf],
+    database: Option<&Database>,
+    archive_dir: Option<&Path>,
+    out: &mut impl Write,
+) -> usize {
+    let mut failures = 0;
+    for ledger_path in find_ledgers(paths) {
+        let shift = match ImportedShift::read(&ledger_path) {
+            Ok(shift) => shift,
+            Err(e) => {
+                error!("couldn't import {ledger_path:?}: {e}");
+                failures += 1;
+                continue;
+            }
+        };
+        if database.is_none() && archive_dir.is_none() {
+            let written: std::io::Result<()> =
+                shift.events().iter().try_for_each(|salvage_event| {
+                    writeln!(out, "{}", salvage_event_json(salvage_event))
+                });
+            if let Err(e) = written {
+                error!("couldn't write out {ledger_path:?}: {e}");
+                failures += 1;
+            }
+            continue;
+        }
+        if let Some(database) = database {
+            match store(&shift, database) {
+                Ok(true) => report(
+                    out,
+                    format_args!(
+                        "stored {ledger_path:?} ({} entries) in the database",
+                        shift.entries.len()
+                    ),
+                ),
+                Ok(false) => report(
+                    out,
+                    format_args!("{ledger_path:?} is in the database already, skipping"),
+                ),
+                Err(e) => {
+                    error!("couldn't store {ledger_path:?} in the database: {e}");
+                    failures += 1;
+                }
+            }
+        }
+        if let Some(archive_dir) = archive_dir {
+            match archive(&shift, archive_dir) {
+                Ok(true) => report(
+                    out,
+                    format_args!("archived {ledger_path:?} ({} entries)", shift.entries.len()),
+                ),
+                Ok(false) => report(
+                    out,
+                    format_args!("{ledger_path:?} is in the archive already, skipping"),
+                ),
+                Err(e) => {
+                    error!("couldn't archive {ledger_path:?}: {e}");
+                    failures += 1;
+                }
+            }
+        }
+    }
+    failures
+}
+
+#[test]
+fn test_import_only_ever_imports_a_shift_once() {
+    use chrono::{Local, TimeZone, Utc};
+    use racers_ledger_datatypes::{ledger_files::LEDGER_CSV_HEADER, SalvageEvent};
+
+    let dir = std::env::temp_dir().join(format!("lamprey-import-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let mod_data = dir.join("mod data");
+    std::fs::create_dir_all(&mod_data).unwrap();
+    let started = Local.with_ymd_and_hms(2021, 7, 4, 12, 34, 56).unwrap();
+    let epoch_time_ms = started.timestamp_millis() + 1000;
+    std::fs::write(
+        mod_data.join("20210704T123456_ledger.csv"),
+        format!(
+            "{LEDGER_CSV_HEADER}\r\n\
+             Hull Plate,10.0,Ferrous,Furnace,300.0,False,False,1.0,{epoch_time_ms}\r\n\
+             Reactor,5.0,Reactor,Furnace,500.0,False,True,2.0,{epoch_time_ms}\r\n"
+        ),
+    )
+    .unwrap();
+    std::fs::write(
+        mod_data.join("20210704T123456_summary.txt"),
+        "EndedBy: complete\nDuration: 00:15:00\nRACE?: False\n",
+    )
+    .unwrap();
+    // not a ledger, so not looked at
+    std::fs::write(mod_data.join("settings.json"), "{}").unwrap();
+    let paths = [mod_data.clone()];
+
+    // neither a database nor an archive: JSON Lines, and that's it
+    let mut out = vec![];
+    assert_eq!(import(&paths, None, None, &mut out), 0);
+    let types: Vec<String> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let json: serde_json::Value = serde_json::from_str(line).unwrap();
+            json["type"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(
+        types,
+        [
+            "startShiftEvent",
+            "shiftSalvageLogEntry",
+            "shiftSalvageLogEntry",
+            "endShiftEvent"
+        ]
+    );
+
+    // importing twice stores and archives it once, and says so
+    let database = Database::open_in_memory().unwrap();
+    let archive_dir = dir.join("archive");
+    let ledger_path = mod_data.join("20210704T123456_ledger.csv");
+    for expected in [
+        [
+            format!("stored {ledger_path:?} (2 entries) in the database"),
+            format!("archived {ledger_path:?} (2 entries)"),
+        ],
+        [
+            format!("{ledger_path:?} is in the database already, skipping"),
+            format!("{ledger_path:?} is in the archive already, skipping"),
+        ],
+    ] {
+        let mut out = vec![];
+        assert_eq!(
+            import(&paths, Some(&database), Some(&archive_dir), &mut out),
+            0
+        );
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().collect::<Vec<_>>(), expected);
+    }
+    let shifts = database.shifts(None).unwrap();
+    assert_eq!(shifts.len(), 1);
+    assert_eq!(shifts[0].totals.destroyed.value, 500.0);
+    assert_eq!(std::fs::read_dir(&archive_dir).unwrap().count(), 1);
+
+    // a shift the lamprey recorded live started a little after the second the file name says
+    let database = Database::open_in_memory().unwrap();
+    let live_started = started.with_timezone(&Utc) + chrono::Duration::milliseconds(345);
+    database
+        .record(
+            None,
+            &SalvageEvent::StartShiftEvent {
+                system_time: live_started,
+            },
+        )
+        .unwrap();
+    assert_eq!(import(&paths, Some(&database), None, &mut vec![]), 0);
+    assert_eq!(database.shifts(None).unwrap().len(), 1);
+
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,288 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::Serialize;
+
+use racers_ledger_datatypes::SalvageEvent;
+
+use super::shift::{SalvageTally, Totals};
+use super::storage::Database;
+
+/// How many objects `LossReport::worst_objects` lists, unless asked for some other number.
+pub const DEFAULT_WORST_OBJECTS: usize = 10;
+
+/// The most objects `/api/v0/losses` lists, however many it gets asked for.
+pub const MAX_WORST_OBJECTS: usize = 1000;
+
+/// How much value got destroyed instead of salvaged, and where: by salvage route (`salvaged_by`), by category and by
+/// object, worst first. For one shift or every shift in the database.
+#[derive(Serialize, Debug, Clone)]
+pub struct LossReport {
+    /// The shift this is about, or `None` for every shift
+    pub shift_id: Option<i64>,
+    #[serde(flatten)]
+    pub totals: SalvageTally,
+    /// Destroyed value as a percentage of everything (salvaged or destroyed) that was worth anything
+    pub percent_lost: Option<f64>,
+    pub by_salvaged_by: Vec<Loss>,
+    pub by_category: Vec<Loss>,
+    /// The objects that lost the most value, at most however many were asked for
+    pub worst_objects: Vec<ObjectLoss>,
+}
+
+/// Losses for one salvage route or category.
+#[derive(Serialize, Debug, Clone)]
+pub struct Loss {
+    pub name: String,
+    #[serde(flatten)]
+    pub totals: SalvageTally,
+    pub percent_lost: Option<f64>,
+}
+
+/// Everything destroyed with the same `object_name`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ObjectLoss {
+    pub object_name: String,
+    pub destroyed: Totals,
+}
+
+/// Salvage entries added up per salvage route and per category, and the worst of the destroyed ones per object:
+/// everything a `LossReport` is worked out from.
+#[derive(Debug, Default)]
+pub struct LossTallies {
+    pub by_salvaged_by: BTreeMap<String, SalvageTally>,
+    pub by_category: BTreeMap<String, SalvageTally>,
+    /// Worst first, only as many as were asked for
+    pub worst_objects: Vec<ObjectLoss>,
+}
+
+fn percent_lost(totals: &SalvageTally) -> Option<f64> {
+    let total = totals.salvaged.value + totals.destroyed.value;
+    (total > 0.0).then(|| totals.destroyed.value / total * 100.0)
+}
+
+/// Worst first, i.e. most destroyed value first, then alphabetically.
+fn worst_first(tallies: BTreeMap<String, SalvageTally>) -> Vec<Loss> {
+    let mut losses: Vec<Loss> = tallies
+        .into_iter()
+        .map(|(name, totals)| Loss {
+            percent_lost: percent_lost(&totals),
+            name,
+            totals,
+        })
+        .collect();
+    losses.sort_by(|a, b| {
+        b.totals
+            .destroyed
+            .value
+            .total_cmp(&a.totals.destroyed.value)
+    });
+    losses
+}
+
+impl LossReport {
+    /// Add up the losses in `entries` (anything that isn't a `ShiftSalvageLogEntry` is ignored), listing the `top`
+    /// worst objects.
+    pub fn new(shift_id: Option<i64>, entries: &[SalvageEvent], top: usize) -> Self {
+        let mut tallies = LossTallies::default();
+        let mut by_object: BTreeMap<String, Totals> = BTreeMap::new();
+        for salvage_event in entries {
+            if let SalvageEvent::ShiftSalvageLogEntry {
+                object_name,
+                mass,
+                categories,
+                salvaged_by,
+                value,
+                destroyed,
+                ..
+            } = salvage_event
+            {
+                tallies
+                    .by_salvaged_by
+                    .entry(salvaged_by.clone())
+                    .or_default()
+                    .add(*value, *mass, *destroyed);
+                for category in categories {
+                    tallies
+                        .by_category
+                        .entry(category.clone())
+                        .or_default()
+                        .add(*value, *mass, *destroyed);
+                }
+                if *destroyed {
+                    by_object
+                        .entry(object_name.clone())
+                        .or_default()
+                        .add(*value, *mass);
+                }
+            }
+        }
+        tallies.worst_objects = by_object
+            .into_iter()
+            .map(|(object_name, destroyed)| ObjectLoss {
+                object_name,
+                destroyed,
+            })
+            .collect();
+        tallies
+            .worst_objects
+            .sort_by(|a, b| b.destroyed.value.total_cmp(&a.destroyed.value));
+        tallies.worst_objects.truncate(top);
+        LossReport::from_tallies(shift_id, tallies)
+    }
+
+    /// The report for losses that were added up already.
+    pub fn from_tallies(shift_id: Option<i64>, tallies: LossTallies) -> Self {
+        // every entry has exactly one salvage route, so those add up to everything
+        let mut totals = SalvageTally::default();
+        for tally in tallies.by_salvaged_by.values() {
+            totals.merge(tally);
+        }
+        LossReport {
+            shift_id,
+            percent_lost: percent_lost(&totals),
+            totals,
+            by_salvaged_by: worst_first(tallies.by_salvaged_by),
+            by_category: worst_first(tallies.by_category),
+            worst_objects: tallies.worst_objects,
+        }
+    }
+
+    /// Losses in the shift with id `shift_id` (`None` if there's no such shift), or in every shift if that's `None`.
+    /// Every shift gets added up by SQLite rather than read in, so there can be as many as you like.
+    ///
+    /// Blocking, like everything `Database`.
+    pub fn from_database(
+        database: &Database,
+        shift_id: Option<i64>,
+        top: usize,
+    ) -> rusqlite::Result<Option<Self>> {
+        match shift_id {
+            Some(shift_id) => Ok(database
+                .shift(shift_id)?
+                .map(|shift| LossReport::new(Some(shift_id), &shift.entries, top))),
+            None => Ok(Some(LossReport::from_tallies(
+                None,
+                database.loss_tallies(top)?,
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for LossReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |percent_lost: Option<f64>| match percent_lost {
+            Some(percent_lost) => format!("{percent_lost:.1}%"),
+            None => "-".to_string(),
+        };
+        match self.shift_id {
+            Some(shift_id) => write!(f, "Shift {shift_id}: ")?,
+            None => write!(f, "Every shift: ")?,
+        }
+        writeln!(
+            f,
+            "destroyed {} items worth {:.0} ({} of all value), salvaged {} items worth {:.0}",
+            self.totals.destroyed.items,
+            self.totals.destroyed.value,
+            percent(self.percent_lost),
+            self.totals.salvaged.items,
+            self.totals.salvaged.value
+        )?;
+        for (heading, losses) in [
+            ("By salvage route", &self.by_salvaged_by),
+            ("By category", &self.by_category),
+        ] {
+            writeln!(f, "\n{heading}:")?;
+            for loss in losses {
+                writeln!(
+                    f,
+                    "  {:<24} {:>6} destroyed  {:>12.0} lost  {:>6} of its value",
+                    loss.name,
+                    loss.totals.destroyed.items,
+                    loss.totals.destroyed.value,
+                    percent(loss.percent_lost)
+                )?;
+            }
+        }
+        writeln!(f, "\nWorst objects:")?;
+        for object in &self.worst_objects {
+            writeln!(
+                f,
+                "  {:<32} {:>6} destroyed  {:>12.0} lost",
+                object.object_name, object.destroyed.items, object.destroyed.value
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_loss_report() {
+    let now = chrono::Utc::now();
+    let entry = |object_name: &str, salvaged_by: &str, categories: &[&str], value, destroyed| {
+        SalvageEvent::ShiftSalvageLogEntry {
+            object_name: object_name.into(),
+            mass: 1.0,
+            categories: categories.iter().map(|c| c.to_string()).collect(),
+            salvaged_by: salvaged_by.into(),
+            value,
+            mass_based_value: false,
+            destroyed,
+            game_time: 1.0,
+            system_time: now,
+        }
+    };
+    let entries = [
+        entry("Hull Plate", "Furnace", &["Ferrous"], 300.0, false),
+        entry("Hull Plate", "Furnace", &["Ferrous"], 100.0, true),
+        entry("Reactor", "Furnace", &["Reactor", "Hazard"], 500.0, true),
+        entry("Seat", "Processor", &["Salvage"], 50.0, false),
+        entry("Fuse", "Processor", &["Salvage"], 10.0, true),
+        // not an entry, so not a loss either (and first, when these get put in the database backwards)
+        SalvageEvent::StartShiftEvent { system_time: now },
+    ];
+    let report = LossReport::new(Some(3), &entries, 2);
+
+    assert_eq!(report.totals.destroyed.value, 610.0);
+    assert_eq!(report.totals.salvaged.value, 350.0);
+    assert_eq!(report.by_salvaged_by[0].name, "Furnace");
+    assert_eq!(report.by_salvaged_by[0].totals.destroyed.items, 2);
+    assert_eq!(
+        report.by_salvaged_by[0].percent_lost,
+        Some(600.0 / 900.0 * 100.0)
+    );
+    // Reactor and Hazard lost the same, so alphabetical
+    let categories: Vec<&str> = report
+        .by_category
+        .iter()
+        .map(|loss| loss.name.as_str())
+        .collect();
+    assert_eq!(categories, ["Hazard", "Reactor", "Ferrous", "Salvage"]);
+    let objects: Vec<&str> = report
+        .worst_objects
+        .iter()
+        .map(|object| object.object_name.as_str())
+        .collect();
+    assert_eq!(objects, ["Reactor", "Hull Plate"]);
+    assert!(report
+        .to_string()
+        .starts_with("Shift 3: destroyed 3 items worth 610"));
+
+    // every shift in the database gets added up by SQLite instead, and had better come out the same
+    let database = Database::open_in_memory().unwrap();
+    let mut shift_id = None;
+    for salvage_event in entries.iter().rev() {
+        shift_id = database.record(shift_id, salvage_event).unwrap();
+    }
+    let from_database = LossReport::from_database(&database, None, 2)
+        .unwrap()
+        .unwrap();
+    let everything = LossReport::new(None, &entries, 2);
+    assert_eq!(
+        serde_json::to_value(&from_database).unwrap(),
+        serde_json::to_value(&everything).unwrap()
+    );
+    assert_eq!(
+        serde_json::to_value(&everything).unwrap()["destroyed"]["value"],
+        610.0
+    );
+}
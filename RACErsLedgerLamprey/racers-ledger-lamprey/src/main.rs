@@ -1,101 +1,540 @@
-use racers_ledger_datatypes::*;
-
-use async_tungstenite::{tokio::connect_async, tungstenite::Message};
-use clap::Parser;
-use futures::prelude::*;
-use serde::Serialize;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
-use tracing::{info, trace, Level};
+use clap::{error::ErrorKind, CommandFactory, FromArgMatches, Parser, Subcommand};
+use racers_ledger_datatypes::{ExitCause, SalvageEvent, ShutdownCause};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr},
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::{oneshot, RwLock};
+use tracing::{error, info, Level};
 use tracing_subscriber::fmt::format::FmtSpan;
 
 #[derive(Parser)]
 #[clap(version = "0.2", author = "Sariya Melody <sariya@sariya.garden>")]
 struct Opts {
-    /// Port for lamprey to connect to and echo events from
-    connect_port: u16,
-    /// Port for lamprey to listen on for subclients (i.e. visualizers, other plugins, etc)
-    listen_port: u16,
+    #[clap(subcommand)]
+    command: Command,
+    /// TOML file to read settings from (see config.example.toml). Anything given on the command line wins.
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
     /// Level of logging verbosity. No -v = Error only, -v = Info, -vv = Debug, -vvv = Trace.
-    #[clap(short, long, action = clap::ArgAction::Count)]
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
     verbose: u8,
-    /// Expose lamprey API on 0.0.0.0 instead of 127.0.0.1?
-    #[clap(long)]
+    /// Address to serve the lamprey API on
+    #[clap(long, global = true, default_value = "127.0.0.1")]
+    listen_address: IpAddr,
+    /// Expose lamprey API on 0.0.0.0 instead of 127.0.0.1? (same as --listen-address 0.0.0.0)
+    #[clap(long, global = true)]
     expose: bool,
-    /// Pick your favorite log format. Options: full (default), compact, pretty, pretty_and_all_spans (warning: noisy)
-    // TODO(sariya) make this an enum somehow lol
-    #[clap(long, default_value = "full")]
-    log_format: String,
+    /// Pick your favorite log format (pretty_and_all_spans is noisy)
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        ignore_case = true,
+        default_value = "full"
+    )]
+    log_format: LogFormat,
     /// Disable colored output.
-    #[clap(long)]
+    #[clap(long, global = true)]
     nocolorize: bool,
-    /// Suppress TimeTickEvent printing to console
-    #[clap(long)]
+    /// Don't print events to the console at all
+    #[clap(long, global = true)]
+    noconsole: bool,
+    /// Suppress TimeTickEvent (and RacePaceEvent and GhostDeltaEvent, which come with every tick in a RACE) printing
+    /// to console
+    #[clap(long, global = true)]
     notime_tick: bool,
+    /// Event types to not print to the console, comma separated (i.e. gameStateChangedEvent,timeTickEvent)
+    #[clap(long, global = true, value_delimiter = ',')]
+    console_skip_types: Vec<String>,
+    /// Directory to archive every event into as JSON Lines, one file per shift. No archiving if not set.
+    #[clap(long, global = true)]
+    archive_dir: Option<PathBuf>,
+    /// Event types to leave out of the archive, comma separated
+    #[clap(long, global = true, value_delimiter = ',')]
+    archive_skip_types: Vec<String>,
+    /// Delete archive files that haven't been written to in this many days. Kept forever if not set.
+    #[clap(long, global = true)]
+    archive_retention_days: Option<NonZeroU32>,
+    /// How many events of the current shift to keep around for proxy clients that connect late
+    #[clap(long, global = true, default_value = "20000")]
+    backlog_size: usize,
+    /// How many events each proxy client can fall behind by before --slow-client-policy kicks in
+    #[clap(long, global = true, default_value = "1000")]
+    client_queue_size: usize,
+    /// What to do with proxy clients that fall too far behind. Options: drop-time-ticks (default, then drop-oldest if
+    /// there are no ticks left), drop-oldest, disconnect
+    #[clap(long, global = true, default_value = "drop-time-ticks")]
+    slow_client_policy: client_queue::SlowClientPolicy,
+    /// SQLite database to keep every shift and everything salvaged in it in, for /api/v0/shifts. Created if it
+    /// doesn't exist. No history if not set.
+    #[clap(long, global = true)]
+    database: Option<PathBuf>,
+    /// Delete shifts that started more than this many days ago from the database. Kept forever if not set.
+    #[clap(long, global = true)]
+    database_retention_days: Option<NonZeroU32>,
+}
+
+/// How log lines look.
+#[derive(clap::ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum LogFormat {
+    Full,
+    Compact,
+    Pretty,
+    /// Pretty, plus every span opening and closing
+    #[value(name = "pretty_and_all_spans", alias = "pretty-and-all-spans")]
+    PrettyAndAllSpans,
+}
+
+/// What the lamprey is here to do.
+#[derive(Subcommand)]
+enum Command {
+    #[clap(flatten)]
+    Serve(ServeCommand),
+    /// Pretend to be the mod (without the game) for testing: serve one shift to whoever connects, then close
+    MockMod {
+        /// Port to serve ws://localhost:<port>/racers-ledger/ on, i.e. what you'd give `connect`
+        port: u16,
+        /// Serve the events in this JSON Lines file instead of making up a random shift
+        #[clap(long)]
+        script: Option<PathBuf>,
+        /// How fast to play the shift: a multiplier of real time like 1x or 10x, or instant
+        #[clap(long, default_value = "1x")]
+        speed: replay::ReplaySpeed,
+        /// Seed for the random shift, so you can get the same one again
+        #[clap(long)]
+        seed: Option<u64>,
+        /// How many items the random shift salvages or destroys
+        #[clap(long, default_value = "50")]
+        items: usize,
+        /// How long the random shift lasts, in (in-game) seconds
+        #[clap(long, default_value = "60")]
+        shift_seconds: NonZeroU32,
+        /// Make the random shift a RACE
+        #[clap(long)]
+        race: bool,
+    },
+    /// Import the mod's own `_ledger.csv`/`_summary.txt` files into --database and/or --archive-dir, or print them as
+    /// JSON Lines if neither is given
+    Import {
+        /// `_ledger.csv` files, or folders full of them (i.e. the mod's data folder)
+        #[clap(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Show how much value got destroyed instead of salvaged in --database, by salvage route, category and object
+    Losses {
+        /// Only look at the shift with this id (see /api/v0/shifts), instead of every shift
+        #[clap(long)]
+        shift: Option<i64>,
+        /// How many of the worst objects to list
+        #[clap(long, default_value_t = losses::DEFAULT_WORST_OBJECTS)]
+        top: usize,
+        /// Print the report as JSON (the same as /api/v0/losses) instead of tables
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+/// Where the lamprey gets its events from, for everything that serves the API.
+#[derive(Subcommand)]
+enum ServeCommand {
+    /// Connect to the mod and proxy everything it says (this is what the mod launches us with)
+    Connect {
+        /// Port for lamprey to connect to and echo events from (required, here or in --config)
+        connect_port: Option<u16>,
+        /// Port for lamprey to listen on for subclients (i.e. visualizers, other plugins, etc) (required, here or in
+        /// --config)
+        listen_port: Option<u16>,
+        /// Host the mod is on
+        #[clap(long, default_value = "localhost")]
+        connect_host: String,
+        /// Path of the mod's websocket
+        #[clap(long, default_value = "/racers-ledger/")]
+        connect_path: String,
+        /// Longest time (in seconds) to wait between attempts to (re)connect to the mod
+        #[clap(long, default_value = "30")]
+        max_reconnect_delay: u64,
+        /// Keep running when the game closes: keep serving the API and wait for the mod to come back, i.e. to run the
+        /// lamprey as a service instead of having the mod launch it
+        #[clap(long)]
+        persist: bool,
+    },
+    /// Play a recorded JSON Lines event log back through the API instead of connecting to the mod
+    Replay {
+        /// JSON Lines file with one event per line (i.e. what --archive-dir writes)
+        file: PathBuf,
+        /// Port for lamprey to listen on for subclients (i.e. visualizers, other plugins, etc) (required, here or in
+        /// --config)
+        listen_port: Option<u16>,
+        /// How fast to play it back: a multiplier of the original spacing like 1x or 10x, or instant
+        #[clap(long, default_value = "1x")]
+        speed: replay::ReplaySpeed,
+        /// Hold off on replaying until at least one proxy client has connected
+        #[clap(long)]
+        wait_for_client: bool,
+    },
+}
+
+impl ServeCommand {
+    /// Where to serve the API, if we know yet (it can come from --config too).
+    fn listen_port(&self) -> Option<u16> {
+        match self {
+            ServeCommand::Connect { listen_port, .. }
+            | ServeCommand::Replay { listen_port, .. } => *listen_port,
+        }
+    }
 }
 
 /// State of currently connected clients.
 ///
 /// Key is "ID" (increasing atomic usize handlers::NEXT_USER_ID) (which is gross and tech debt but whatever i'm not dealing with this right now)
-/// Value is a handle to send things to that client.
-pub type Clients =
-    Arc<RwLock<HashMap<usize, mpsc::UnboundedSender<Result<warp::ws::Message, warp::Error>>>>>;
+/// Value is a handle to send things to that client, plus what it wants to hear about.
+pub type Clients = Arc<RwLock<HashMap<usize, Client>>>;
+
+/// What we hand each proxy client's connection, which turns it into whatever that client speaks (websocket, SSE...)
+#[derive(Debug, Clone)]
+pub enum ProxyMessage {
+    /// A ledger event, with its sequence number (and its JSON, shared by every client that gets it)
+    Event(envelope::SharedEnvelope),
+    /// The lamprey's own events for this client, like the `LampreyWelcomeEvent` before anything else. Not part of the
+    /// stream, so no sequence number.
+    Lamprey(SalvageEvent),
+    /// Nothing more is coming, hang up
+    Close { code: u16, reason: String },
+}
+
+/// One connected proxy client.
+#[derive(Debug)]
+pub struct Client {
+    pub tx: client_queue::ClientSender,
+    /// Event `type`s this client subscribed to (i.e. `shiftSalvageLogEntry`). `None` means it wants everything.
+    pub subscriptions: Option<HashSet<String>>,
+}
+
+impl Client {
+    /// Does this client care about this event?
+    pub fn wants(&self, salvage_event: &SalvageEvent) -> bool {
+        match &self.subscriptions {
+            Some(subscriptions) => subscriptions.contains(salvage_event.event_type()),
+            None => true,
+        }
+    }
+}
+
+/// The current shift's events, for proxy clients that connect late. See `backlog::ShiftBacklog`.
+///
+/// If you need both this and `Clients` locked, lock this one first.
+pub type Backlog = Arc<RwLock<backlog::ShiftBacklog>>;
 
-/// Data about the current state-of-the-world. Right now it's just if we're in shift or not. Maybe more eventually.
+/// Send every proxy client a close frame, i.e. when there's nothing more coming.
+pub async fn disconnect_all_clients(clients: &Clients, code: u16, reason: &str) {
+    for client in clients.read().await.values() {
+        if let Err(_disconnected) = client.tx.send(ProxyMessage::Close {
+            code,
+            reason: reason.to_string(),
+        }) {
+            // the tx is disconnected and already gone
+        }
+    }
+}
+
+/// Version of the HTTP API, i.e. the `v0` in `/api/v0`.
+pub const API_VERSION: u32 = 0;
+
+/// Data about the current state-of-the-world. Right now it's if we're in shift or not, if we can hear the mod and what it told us about itself. Maybe more eventually.
 #[derive(Default, Serialize, Debug)]
 pub struct LedgerState {
     in_shift: bool,
+    /// Why the most recently ended shift ended, if the mod said.
+    last_exit_cause: Option<ExitCause>,
+    upstream_connected: bool,
+    /// What the mod said about itself when we connected, if we're connected.
+    upstream: Option<upstream::UpstreamInfo>,
 }
 /// Utility type for what we're actually going to be passing around.
 pub type State = Arc<RwLock<LedgerState>>;
 
+/// `upstream` is the "mod websocket", i.e. our connection to the mod itself and keeping it alive.
+mod upstream;
+
+/// `replay` pretends to be the mod by playing back recorded events from a file.
+mod replay;
+
+/// `mock_mod` pretends to be the mod a different way: by serving the same websocket the mod does.
+mod mock_mod;
+
+/// `envelope` stamps every event with a sequence number on its way into the lamprey, so missing ones stand out.
+mod envelope;
+
+/// `client_queue` is where each proxy client's messages wait until it's ready for them, and what happens if it isn't.
+mod client_queue;
+
+/// `shift` keeps running totals for a shift as events come in.
+mod shift;
+
+/// `backlog` remembers the current shift's events for proxy clients that show up late.
+mod backlog;
+
+/// `storage` keeps every shift in SQLite, so there's something to look back on.
+mod storage;
+
+/// `ghost` races the current RACE against the best recorded run of the same one.
+mod ghost;
+
+/// `losses` works out what got destroyed instead of salvaged, and where.
+mod losses;
+
+/// `metrics` counts things for Prometheus.
+mod metrics;
+
+/// `import` brings in shifts the mod wrote to disk itself, from before the lamprey was keeping track.
+mod import;
+
+/// `retention` throws away archive files and stored shifts once they're old enough.
+mod retention;
+
+/// `shutdown` winds everything down when the mod goes away or we're asked to stop.
+mod shutdown;
+
+/// `config` reads settings from a TOML file, for setups worth checking in.
+mod config;
+
 /// `filters` is all about Warp routing and how we set it up.
 /// API endpoints:
 /// - /api/v0/status: Emits the data described in `LedgerState`
+/// - /api/v0/shift/current: Emits the running totals for the current shift (see `shift::ShiftAggregate`)
 /// - /api/v0/racers-ledger-proxy: Websocket endpoint. All data the Lamprey gets from the mod is echoed here.
+///   `?since=shift_start` or `?since=<seq>` replays what the client missed first, `?types=a,b` only sends those types,
+///   `?envelope=true` wraps every event in its `envelope::Envelope`.
+/// - /api/v0/events: Server-Sent Events version of racers-ledger-proxy, for things that'd rather not do websockets.
+///   Takes the same query string, and `Last-Event-ID` for resuming.
+/// - /api/v0/shifts: Every shift in the database, newest first (see `storage::StoredShift`). `?limit=n` for fewer.
+/// - /api/v0/shifts/<id>: One shift from the database, with all of its salvage entries.
+/// - /api/v0/losses: What got destroyed instead of salvaged, by salvage route, category and object (see
+///   `losses::LossReport`). `?shift=<id>` for one shift instead of all of them, `?top=n` for more or fewer objects.
+/// - /metrics: Prometheus metrics (see `metrics::Metrics`)
 mod filters {
-    use std::convert::Infallible;
+    use std::{collections::HashSet, convert::Infallible};
+
+    use serde::Deserialize;
 
+    use super::backlog::Since;
+    use super::client_queue::QueueOptions;
+    use super::envelope::CurrentShift;
     use super::handlers;
+    use super::metrics::Metrics;
+    use super::storage::Database;
+    use super::Backlog;
     use super::Clients;
     use super::State;
     use warp::Filter;
 
+    /// Query string for /api/v0/racers-ledger-proxy and /api/v0/events
+    #[derive(Deserialize, Debug)]
+    pub struct LedgerProxyQuery {
+        pub since: Option<Since>,
+        /// Comma-separated event types to subscribe to, i.e. `shiftSalvageLogEntry,endShiftEvent`
+        types: Option<String>,
+        /// Send every event wrapped in its `Envelope` (sequence number and all) instead of on its own
+        #[serde(default)]
+        pub envelope: bool,
+    }
+
+    impl LedgerProxyQuery {
+        pub fn subscriptions(&self) -> Option<HashSet<String>> {
+            self.types.as_ref().map(|types| {
+                types
+                    .split(',')
+                    .map(|event_type| event_type.trim().to_string())
+                    .filter(|event_type| !event_type.is_empty())
+                    .collect()
+            })
+        }
+    }
+
+    /// Query string for /api/v0/shifts
+    #[derive(Deserialize, Debug)]
+    pub struct ShiftsQuery {
+        pub limit: Option<u32>,
+    }
+
+    /// Query string for /api/v0/losses
+    #[derive(Deserialize, Debug)]
+    pub struct LossesQuery {
+        pub shift: Option<i64>,
+        pub top: Option<usize>,
+    }
+
     /// Describes the entire API we're exporting.
     #[tracing::instrument]
     pub fn api(
         state: State,
+        shift_totals: CurrentShift,
         clients: Clients,
+        backlog: Backlog,
+        database: Option<Database>,
+        metrics: Metrics,
+        queue_options: QueueOptions,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        warp::path("api")
-            .and(warp::path("v0").and(status(state.clone()).or(ledger_proxy(clients.clone()))))
+        let api = warp::path("api").and(
+            warp::path("v0").and(
+                status(state.clone(), clients.clone())
+                    .or(current_shift(shift_totals))
+                    .or(ledger_proxy(
+                        clients.clone(),
+                        backlog.clone(),
+                        state.clone(),
+                        queue_options,
+                    ))
+                    .or(events(
+                        clients.clone(),
+                        backlog.clone(),
+                        state.clone(),
+                        queue_options,
+                    ))
+                    .or(shifts(database.clone()))
+                    .or(shift(database.clone()))
+                    .or(losses(database)),
+            ),
+        );
+        api.or(prometheus_metrics(metrics, clients))
     }
 
     /// route /api/v0/status
     #[tracing::instrument]
     pub fn status(
         state: State,
+        clients: Clients,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("status")
             .and(warp::path::end())
             .and(warp::get())
             .and(with_state(state.clone()))
+            .and(with_clients(clients))
             .and_then(handlers::handle_status)
     }
 
+    /// route /api/v0/shift/current
+    #[tracing::instrument]
+    pub fn current_shift(
+        shift_totals: CurrentShift,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("shift" / "current")
+            .and(warp::get())
+            .and(warp::any().map(move || shift_totals.clone()))
+            .and_then(handlers::handle_current_shift)
+    }
+
     /// route /api/v0/racers-ledger-proxy
     #[tracing::instrument]
     pub fn ledger_proxy(
         clients: Clients,
+        backlog: Backlog,
+        state: State,
+        queue_options: QueueOptions,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("racers-ledger-proxy")
             .and(warp::ws())
+            .and(warp::query::<LedgerProxyQuery>())
             .and(with_clients(clients))
-            .map(move |ws: warp::ws::Ws, clients| {
-                ws.on_upgrade(move |socket| {
-                    handlers::handle_websocket_ledger_proxy_connected(socket, clients)
-                })
-            })
+            .and(with_backlog(backlog))
+            .and(with_state(state))
+            .map(
+                move |ws: warp::ws::Ws, query: LedgerProxyQuery, clients, backlog, state| {
+                    ws.on_upgrade(move |socket| {
+                        handlers::handle_websocket_ledger_proxy_connected(
+                            socket,
+                            clients,
+                            backlog,
+                            state,
+                            queue_options,
+                            query,
+                        )
+                    })
+                },
+            )
+    }
+
+    /// route /api/v0/events
+    #[tracing::instrument]
+    pub fn events(
+        clients: Clients,
+        backlog: Backlog,
+        state: State,
+        queue_options: QueueOptions,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("events")
+            .and(warp::get())
+            .and(warp::query::<LedgerProxyQuery>())
+            .and(warp::header::optional::<u64>("last-event-id"))
+            .and(with_clients(clients))
+            .and(with_backlog(backlog))
+            .and(with_state(state))
+            .and_then(
+                move |mut query: LedgerProxyQuery,
+                      last_event_id: Option<u64>,
+                      clients,
+                      backlog,
+                      state| {
+                    // a browser reconnecting knows better than whatever the original URL said
+                    query.since = last_event_id.map(Since::Seq).or(query.since);
+                    handlers::handle_events_connected(clients, backlog, state, queue_options, query)
+                },
+            )
+    }
+
+    /// route /api/v0/shifts
+    #[tracing::instrument]
+    pub fn shifts(
+        database: Option<Database>,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("shifts")
+            .and(warp::get())
+            .and(warp::query::<ShiftsQuery>())
+            .and(with_database(database))
+            .and_then(handlers::handle_shifts)
+    }
+
+    /// route /api/v0/shifts/<id>
+    #[tracing::instrument]
+    pub fn shift(
+        database: Option<Database>,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("shifts" / i64)
+            .and(warp::get())
+            .and(with_database(database))
+            .and_then(handlers::handle_shift)
+    }
+
+    /// route /api/v0/losses
+    #[tracing::instrument]
+    pub fn losses(
+        database: Option<Database>,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("losses")
+            .and(warp::get())
+            .and(warp::query::<LossesQuery>())
+            .and(with_database(database))
+            .and_then(handlers::handle_losses)
+    }
+
+    /// route /metrics (not under /api/v0, since that's where Prometheus looks by default)
+    #[tracing::instrument]
+    pub fn prometheus_metrics(
+        metrics: Metrics,
+        clients: Clients,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("metrics")
+            .and(warp::get())
+            .and(warp::any().map(move || metrics.clone()))
+            .and(with_clients(clients))
+            .and_then(handlers::handle_metrics)
     }
 
     /// Warp filter for adding in a State
@@ -104,6 +543,22 @@ mod filters {
         warp::any().map(move || state.clone())
     }
 
+    /// Warp filter for adding in a Backlog
+    #[tracing::instrument]
+    fn with_backlog(
+        backlog: Backlog,
+    ) -> impl Filter<Extract = (Backlog,), Error = Infallible> + Clone {
+        warp::any().map(move || backlog.clone())
+    }
+
+    /// Warp filter for adding in the Database, if there is one
+    #[tracing::instrument]
+    fn with_database(
+        database: Option<Database>,
+    ) -> impl Filter<Extract = (Option<Database>,), Error = Infallible> + Clone {
+        warp::any().map(move || database.clone())
+    }
+
     /// Warp filter for adding in a Clients
     #[tracing::instrument]
     fn with_clients(
@@ -116,51 +571,184 @@ mod filters {
 /// `handlers` is all about responding to connections that were routed to us via `filters`.
 mod handlers {
     use std::{
+        collections::HashSet,
         convert::Infallible,
         sync::atomic::{AtomicUsize, Ordering},
     };
 
-    use futures::{FutureExt, StreamExt};
+    use futures::{future, FutureExt, StreamExt};
     use log::{debug, error, info};
-    use tokio::sync::mpsc;
-    use tokio_stream::wrappers::UnboundedReceiverStream;
-    use warp::ws::WebSocket;
+    use serde::{Deserialize, Serialize};
+    use warp::{http::StatusCode, sse, ws::WebSocket, Reply};
+
+    use chrono::Utc;
+    use racers_ledger_datatypes::{SalvageEvent, PROTOCOL_VERSION};
 
+    use super::client_queue::{self, ClientReceiver, QueueOptions};
+    use super::envelope::{CurrentShift, Envelope, SharedEnvelope};
+    use super::filters::{LedgerProxyQuery, LossesQuery, ShiftsQuery};
+    use super::losses::{LossReport, DEFAULT_WORST_OBJECTS, MAX_WORST_OBJECTS};
+    use super::metrics::Metrics;
+    use super::storage::Database;
+    use super::Backlog;
+    use super::Client;
     use super::Clients;
+    use super::LedgerState;
+    use super::ProxyMessage;
     use super::State;
+    use super::API_VERSION;
+
+    /// Things proxy clients can tell us over their websocket.
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase", tag = "type")]
+    enum ClientMessage {
+        /// Only send me these event types from now on, i.e. `{"type":"subscribe","events":["endShiftEvent"]}`
+        Subscribe { events: HashSet<String> },
+        /// Go back to sending me everything.
+        SubscribeAll,
+        /// Send me these events again (if they're still in the backlog), i.e. after noticing a gap in the sequence
+        /// numbers. `to` is inclusive, and everything after `from` if it's left out.
+        Resend { from: u64, to: Option<u64> },
+    }
 
     /// global unique user id counter, key for Clients
     static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
 
-    /// When websocket clients connect, stick 'em in Clients.
-    #[tracing::instrument]
-    pub async fn handle_websocket_ledger_proxy_connected(websocket: WebSocket, clients: Clients) {
+    /// Catch a new proxy client up on the backlog if they asked for it and stick 'em in Clients.
+    /// Returns their ID and where their messages will show up.
+    async fn register_client(
+        clients: &Clients,
+        backlog: &Backlog,
+        state: &State,
+        queue_options: QueueOptions,
+        query: &LedgerProxyQuery,
+    ) -> (usize, ClientReceiver) {
         let my_id = NEXT_USER_ID.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = client_queue::channel(queue_options);
+        let client = Client {
+            tx,
+            subscriptions: query.subscriptions(),
+        };
+        // say hi first, whatever they subscribed to, so they can tell if they understand us before anything else
+        let welcome = SalvageEvent::LampreyWelcomeEvent {
+            lamprey_version: env!("CARGO_PKG_VERSION").into(),
+            api_version: API_VERSION,
+            protocol_version: PROTOCOL_VERSION,
+            state: serde_json::to_value(&*state.read().await).unwrap_or_default(),
+            system_time: Utc::now(),
+        };
+        if let Err(_disconnected) = client.tx.send(ProxyMessage::Lamprey(welcome)) {
+            // the tx is disconnected.
+        }
+        // hold the backlog lock until we're in Clients, so nothing slips through the cracks between the two
+        let backlog = backlog.read().await;
+        if let Some(since) = query.since {
+            let missed = backlog.since(since);
+            debug!("catching client {my_id} up on {} events", missed.len());
+            for envelope in missed.into_iter().filter(|e| client.wants(&e.event)) {
+                if let Err(_disconnected) = client.tx.send_catch_up(ProxyMessage::Event(envelope)) {
+                    // the tx is disconnected.
+                }
+            }
+        }
+        clients.write().await.insert(my_id, client);
+        (my_id, rx)
+    }
+
+    /// When websocket clients connect, register them and listen for what they want.
+    #[tracing::instrument]
+    pub async fn handle_websocket_ledger_proxy_connected(
+        websocket: WebSocket,
+        clients: Clients,
+        backlog: Backlog,
+        state: State,
+        queue_options: QueueOptions,
+        query: LedgerProxyQuery,
+    ) {
         let (user_ws_tx, mut user_ws_rx) = websocket.split();
-        let (tx, rx) = mpsc::unbounded_channel();
-        let rx = UnboundedReceiverStream::new(rx);
+        let (my_id, rx) = register_client(&clients, &backlog, &state, queue_options, &query).await;
+        let wants_envelope = query.envelope;
+        let rx = rx.into_stream().map(move |proxy_message| {
+            Ok(match proxy_message {
+                // warp wants its own String, so this still copies the JSON for every client, but that beats
+                // serializing it again
+                ProxyMessage::Event(envelope) => {
+                    warp::ws::Message::text(&*envelope.json(wants_envelope))
+                }
+                ProxyMessage::Lamprey(salvage_event) => {
+                    warp::ws::Message::text(&*lamprey_envelope(salvage_event).json(wants_envelope))
+                }
+                ProxyMessage::Close { code, reason } => warp::ws::Message::close_with(code, reason),
+            })
+        });
         debug!("new client connected wooooo");
         tokio::task::spawn(rx.forward(user_ws_tx).map(|result| {
             if let Err(e) = result {
                 error!("websocket send error: {e}");
             }
         }));
-        clients.write().await.insert(my_id, tx);
         while let Some(result) = user_ws_rx.next().await {
             match result {
                 Err(e) => {
                     error!("websocket error (uid={my_id}): {e}");
                     break;
                 }
-                _ => {
-                    // successfull responses from the downstream clients are ignored, since
-                    // we do can not know what they are trying to tell us
+                Ok(msg) => {
+                    // anything we don't understand is ignored, since we do can not know what they are trying to
+                    // tell us
+                    let client_message =
+                        match msg.to_str().map(serde_json::from_str::<ClientMessage>) {
+                            Ok(Ok(client_message)) => client_message,
+                            _ => {
+                                debug!("ignoring message from client {my_id}: {msg:?}");
+                                continue;
+                            }
+                        };
+                    debug!("client {my_id} says {client_message:?}");
+                    match client_message {
+                        ClientMessage::Subscribe { events } => {
+                            if let Some(client) = clients.write().await.get_mut(&my_id) {
+                                client.subscriptions = Some(events);
+                            }
+                        }
+                        ClientMessage::SubscribeAll => {
+                            if let Some(client) = clients.write().await.get_mut(&my_id) {
+                                client.subscriptions = None;
+                            }
+                        }
+                        ClientMessage::Resend { from, to } => {
+                            // backlog before clients, see Backlog
+                            let backlog = backlog.read().await;
+                            if let Some(client) = clients.read().await.get(&my_id) {
+                                let resent = backlog.between(from, to.unwrap_or(u64::MAX));
+                                debug!("resending client {my_id} {} events", resent.len());
+                                for envelope in
+                                    resent.into_iter().filter(|e| client.wants(&e.event))
+                                {
+                                    if let Err(_disconnected) =
+                                        client.tx.send_catch_up(ProxyMessage::Event(envelope))
+                                    {
+                                        // the tx is disconnected.
+                                    }
+                                }
+                            }
+                        }
+                    }
                 }
             };
         }
         handle_websocket_ledger_proxy_disconnected(my_id, &clients).await;
     }
 
+    /// The lamprey's own events aren't part of the stream, so they get the sequence number no real event has.
+    fn lamprey_envelope(salvage_event: SalvageEvent) -> SharedEnvelope {
+        SharedEnvelope::new(Envelope {
+            seq: 0,
+            received_time: Utc::now(),
+            event: salvage_event,
+        })
+    }
+
     /// Internal helper function for its `connected` counterpart.
     #[tracing::instrument]
     async fn handle_websocket_ledger_proxy_disconnected(my_id: usize, clients: &Clients) {
@@ -168,130 +756,599 @@ mod handlers {
         clients.write().await.remove(&my_id);
     }
 
+    /// Takes an SSE client back out of Clients once its stream goes away (i.e. the client disconnected).
+    struct SseClientGuard {
+        my_id: usize,
+        clients: Clients,
+    }
+
+    impl Drop for SseClientGuard {
+        fn drop(&mut self) {
+            let (my_id, clients) = (self.my_id, self.clients.clone());
+            tokio::spawn(async move {
+                info!("disconnecting SSE user {my_id}");
+                clients.write().await.remove(&my_id);
+            });
+        }
+    }
+
+    /// When SSE clients connect, register them and stream them events until one of us hangs up.
+    #[tracing::instrument]
+    pub async fn handle_events_connected(
+        clients: Clients,
+        backlog: Backlog,
+        state: State,
+        queue_options: QueueOptions,
+        query: LedgerProxyQuery,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let (my_id, rx) = register_client(&clients, &backlog, &state, queue_options, &query).await;
+        debug!("new SSE client connected wooooo");
+        let wants_envelope = query.envelope;
+        let guard = SseClientGuard { my_id, clients };
+        let stream = rx
+            .into_stream()
+            .take_while(|proxy_message| {
+                future::ready(!matches!(proxy_message, ProxyMessage::Close { .. }))
+            })
+            .filter_map(move |proxy_message| {
+                let _guard = &guard;
+                future::ready(match proxy_message {
+                    ProxyMessage::Event(envelope) => Some(Ok::<_, Infallible>(
+                        sse::Event::default()
+                            .id(envelope.seq.to_string())
+                            .event(envelope.event.event_type())
+                            .data(&*envelope.json(wants_envelope)),
+                    )),
+                    // no id, so it doesn't mess with Last-Event-ID
+                    ProxyMessage::Lamprey(salvage_event) => Some(Ok(sse::Event::default()
+                        .event(salvage_event.event_type())
+                        .data(&*lamprey_envelope(salvage_event).json(wants_envelope)))),
+                    ProxyMessage::Close { .. } => None,
+                })
+            });
+        Ok(sse::reply(sse::keep_alive().stream(stream)))
+    }
+
     /// When clients query for status via the API, here's how it gets to them.
     #[tracing::instrument]
-    pub async fn handle_status(state: State) -> Result<impl warp::Reply, Infallible> {
+    pub async fn handle_status(
+        state: State,
+        clients: Clients,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let mut proxy_clients: Vec<ProxyClientStatus> = clients
+            .read()
+            .await
+            .iter()
+            .map(|(id, client)| ProxyClientStatus {
+                id: *id,
+                queued: client.tx.queued(),
+                dropped: client.tx.dropped(),
+            })
+            .collect();
+        proxy_clients.sort_by_key(|proxy_client| proxy_client.id);
         let state = state.read().await;
-        Ok(warp::reply::json(&*state))
+        Ok(warp::reply::json(&StatusReply {
+            state: &state,
+            proxy_clients,
+        }))
+    }
+
+    /// What /api/v0/status says: the `LedgerState`, plus how each proxy client is keeping up.
+    #[derive(Serialize, Debug)]
+    struct StatusReply<'a> {
+        #[serde(flatten)]
+        state: &'a LedgerState,
+        proxy_clients: Vec<ProxyClientStatus>,
+    }
+
+    #[derive(Serialize, Debug)]
+    struct ProxyClientStatus {
+        id: usize,
+        /// Messages waiting to be sent to it right now
+        queued: usize,
+        /// Events it missed out on because it fell too far behind, see `client_queue::SlowClientPolicy`
+        dropped: u64,
+    }
+
+    /// Running totals for the current shift, or the last one if we're between shifts. `null` if we've never seen one,
+    /// or the mod went away in the middle of it.
+    #[tracing::instrument]
+    pub async fn handle_current_shift(
+        current_shift: CurrentShift,
+    ) -> Result<impl warp::Reply, Infallible> {
+        Ok(warp::reply::json(&current_shift.get()))
+    }
+
+    /// Everything in `Metrics`, for Prometheus to scrape.
+    #[tracing::instrument]
+    pub async fn handle_metrics(
+        metrics: Metrics,
+        clients: Clients,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let proxy_clients = clients.read().await.len();
+        Ok(warp::reply::with_header(
+            metrics.encode(proxy_clients),
+            "content-type",
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        ))
+    }
+
+    /// Every shift we've got stored, newest first. 404 if there's no database.
+    #[tracing::instrument]
+    pub async fn handle_shifts(
+        query: ShiftsQuery,
+        database: Option<Database>,
+    ) -> Result<warp::reply::Response, warp::Rejection> {
+        let database = database.ok_or_else(warp::reject::not_found)?;
+        let shifts = tokio::task::spawn_blocking(move || database.shifts(query.limit)).await;
+        database_reply(shifts.map(|shifts| shifts.map(Some)))
+    }
+
+    /// One stored shift and everything in it. 404 if there's no database or no such shift.
+    #[tracing::instrument]
+    pub async fn handle_shift(
+        shift_id: i64,
+        database: Option<Database>,
+    ) -> Result<warp::reply::Response, warp::Rejection> {
+        let database = database.ok_or_else(warp::reject::not_found)?;
+        database_reply(tokio::task::spawn_blocking(move || database.shift(shift_id)).await)
+    }
+
+    /// What got destroyed instead of salvaged, in one shift or all of them. 404 if there's no database or no such
+    /// shift.
+    #[tracing::instrument]
+    pub async fn handle_losses(
+        query: LossesQuery,
+        database: Option<Database>,
+    ) -> Result<warp::reply::Response, warp::Rejection> {
+        let database = database.ok_or_else(warp::reject::not_found)?;
+        let top = query
+            .top
+            .unwrap_or(DEFAULT_WORST_OBJECTS)
+            .min(MAX_WORST_OBJECTS);
+        database_reply(
+            tokio::task::spawn_blocking(move || {
+                LossReport::from_database(&database, query.shift, top)
+            })
+            .await,
+        )
+    }
+
+    /// Turn the result of a database query into a response: JSON if we found something, 404 if we didn't, 500 if
+    /// the database (or the blocking task talking to it) fell over.
+    fn database_reply<T: Serialize>(
+        result: Result<rusqlite::Result<Option<T>>, tokio::task::JoinError>,
+    ) -> Result<warp::reply::Response, warp::Rejection> {
+        match result {
+            Ok(Ok(Some(found))) => Ok(warp::reply::json(&found).into_response()),
+            Ok(Ok(None)) => Err(warp::reject::not_found()),
+            Ok(Err(e)) => {
+                error!("database query failed: {e}");
+                Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            }
+            Err(e) => {
+                error!("database query task failed: {e}");
+                Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            }
+        }
     }
 }
 
 /// `sinks` is all of the long-running internal "helper processes" that keep an eye on what's happening in the
 /// `ledger_events_receiver` broadcast channel and help accordingly.
 mod sinks {
+    use super::envelope::{Envelope, LedgerEventsReceiver, SharedEnvelope, WeakLedgerEventsSender};
+    use super::ghost::{Ghost, GhostRace};
+    use super::metrics::Metrics;
+    use super::storage::Database;
+    use super::upstream::UpstreamInfo;
+    use super::Backlog;
     use super::Clients;
+    use super::ProxyMessage;
     use super::State;
-    use log::{debug, error, trace};
+    use chrono::{DateTime, Local, Utc};
+    use log::{debug, error, info, trace};
     use serde_json::json;
-    use tokio::sync::broadcast::{error::RecvError, Receiver};
-    use warp::ws::Message;
+    use std::{collections::HashSet, path::PathBuf};
+    use tokio::{
+        fs::{File, OpenOptions},
+        io::{AsyncWriteExt, BufWriter},
+    };
 
     use racers_ledger_datatypes::SalvageEvent;
 
     /// Handles actually telling our proxy clients about ledger event updates.
     #[tracing::instrument]
     pub async fn websocket_client_updater_sink(
-        mut ledger_events_receiver: Receiver<SalvageEvent>,
+        mut ledger_events_receiver: LedgerEventsReceiver,
         clients: Clients,
+        backlog: Backlog,
     ) {
-        loop {
-            let recv_result = ledger_events_receiver.recv().await;
-            match recv_result {
-                Ok(salvage_event) => {
-                    for (client_id, tx) in clients.read().await.iter() {
-                        debug!("attempted to send data to client {client_id}");
-                        let json = serde_json::to_string(&salvage_event).unwrap_or_else(|_| {
-                            error!("somehow failed to serialize salvage event to string: {salvage_event:#?}");
-                            json!({
-                                "type": "error",
-                                "message": "could not serialize salvage event :("
-                            })
-                            .to_string()
-                        });
-                        if let Err(_disconnected) = tx.send(Ok(Message::text(json))) {
-                            // the tx is disconnected.
-                        }
-                    }
+        while let Some(envelope) = ledger_events_receiver.recv().await {
+            // serialized at most once, the first time a client needs it, however many clients there are
+            let envelope = SharedEnvelope::new(envelope);
+            // keep the backlog locked while we send, see handlers::register_client
+            let mut backlog = backlog.write().await;
+            backlog.push(envelope.clone());
+            for (client_id, client) in clients.read().await.iter() {
+                if !client.wants(&envelope.event) {
+                    continue;
                 }
-                Err(RecvError::Lagged(lagged_messages)) => {
-                    error!("websocket client updater sink missed {lagged_messages} messages :(")
-                }
-                Err(RecvError::Closed) => {
-                    error!("somehow the websocket client updater sink got a RecvError::Closed, this is a problem if it happened when not shutting down the game, bug sariya about it");
+                debug!("attempted to send data to client {client_id}");
+                if let Err(_disconnected) = client.tx.send(ProxyMessage::Event(envelope.clone())) {
+                    // the tx is disconnected.
                 }
             }
         }
     }
 
-    /// Log to the console!
+    /// Turn a salvage event into JSON for proxy clients.
+    pub fn salvage_event_json(salvage_event: &SalvageEvent) -> String {
+        serde_json::to_string(salvage_event).unwrap_or_else(|_| {
+            error!("somehow failed to serialize salvage event to string: {salvage_event:#?}");
+            json!({
+                "type": "error",
+                "message": "could not serialize salvage event :("
+            })
+            .to_string()
+        })
+    }
+
+    /// Log to the console! Everything but the `skip_types`, anyway.
     #[tracing::instrument]
     pub async fn console_sink(
-        mut ledger_events_receiver: Receiver<SalvageEvent>,
-        log_time_tick: bool,
+        mut ledger_events_receiver: LedgerEventsReceiver,
+        skip_types: HashSet<String>,
     ) {
-        loop {
-            let recv_result = ledger_events_receiver.recv().await;
-            match recv_result {
-                Ok(salvage_event) => match salvage_event {
-                    SalvageEvent::TimeTickEvent { .. } => {
-                        trace!("received {salvage_event:#?}");
-                        if log_time_tick {
-                            println!("{salvage_event}")
+        while let Some(Envelope {
+            event: salvage_event,
+            ..
+        }) = ledger_events_receiver.recv().await
+        {
+            trace!("received {salvage_event:#?}");
+            if !skip_types.contains(salvage_event.event_type()) {
+                println!("{salvage_event}")
+            }
+        }
+    }
+
+    /// Write every ledger event (but the `skip_types`) to disk as JSON Lines, one file per shift, so that a crash
+    /// mid-shift doesn't lose everything (the mod only writes its CSV at the end of a shift).
+    #[tracing::instrument]
+    pub async fn json_lines_archive_sink(
+        mut ledger_events_receiver: LedgerEventsReceiver,
+        archive_dir: PathBuf,
+        skip_types: HashSet<String>,
+    ) {
+        if let Err(e) = tokio::fs::create_dir_all(&archive_dir).await {
+            error!(
+                "couldn't create archive directory {archive_dir:?}, not archiving anything: {e}"
+            );
+            return;
+        }
+        let mut archive = JsonLinesArchive::new(archive_dir, skip_types);
+        while let Some(Envelope {
+            event: salvage_event,
+            ..
+        }) = ledger_events_receiver.recv().await
+        {
+            if let Err(e) = archive.record(&salvage_event).await {
+                error!("failed archiving {salvage_event:?}: {e}");
+            }
+        }
+        if let Err(e) = archive.close().await {
+            error!("failed closing the archive: {e}");
+        }
+    }
+
+    /// The file the archive sink is currently appending to, and where it's going next.
+    #[derive(Debug)]
+    struct JsonLinesArchive {
+        archive_dir: PathBuf,
+        /// Event types that don't get written down (they still rotate files if they start or end shifts).
+        skip_types: HashSet<String>,
+        current: Option<(PathBuf, BufWriter<File>)>,
+        /// Start of the shift we're in, if we're in one. Needed to rename the file once RACE info shows up.
+        shift_started: Option<DateTime<Utc>>,
+    }
+
+    impl JsonLinesArchive {
+        fn new(archive_dir: PathBuf, skip_types: HashSet<String>) -> Self {
+            JsonLinesArchive {
+                archive_dir,
+                skip_types,
+                current: None,
+                shift_started: None,
+            }
+        }
+
+        /// Append one event, rotating files on shift boundaries.
+        async fn record(&mut self, salvage_event: &SalvageEvent) -> std::io::Result<()> {
+            match salvage_event {
+                SalvageEvent::StartShiftEvent { system_time } => {
+                    self.close().await?;
+                    self.shift_started = Some(*system_time);
+                    self.open(archive_file_name(*system_time, None)).await?;
+                }
+                SalvageEvent::SetRACEInfoEvent { version, .. } => {
+                    if let Some(shift_started) = self.shift_started {
+                        // RACE info shows up after the shift already started, so move what we have so far over.
+                        // Windows won't let us rename open files, so close it first.
+                        let new_path = self
+                            .archive_dir
+                            .join(archive_file_name(shift_started, Some(*version)));
+                        if let Some(old_path) = self.close().await? {
+                            if old_path != new_path {
+                                tokio::fs::rename(&old_path, &new_path).await?;
+                                info!("renamed archive {old_path:?} to {new_path:?}");
+                            }
                         }
+                        self.open_path(new_path).await?;
                     }
-                    salvage_event => {
-                        trace!("received {salvage_event:#?}");
-                        println!("{salvage_event}")
-                    }
-                },
-                Err(RecvError::Lagged(lagged_messages)) => {
-                    error!("console sink missed {lagged_messages} messages :(")
                 }
-                Err(RecvError::Closed) => {
-                    error!("somehow the console sink got a RecvError::Closed, this is a problem if it happened when not shutting down the game, bug sariya about it");
+                _ => {}
+            }
+            let skipped = self.skip_types.contains(salvage_event.event_type());
+            if self.current.is_none() && !skipped {
+                // events outside of shifts (menus, the hab, ...) go in their own file
+                self.open(format!(
+                    "{}_between_shifts_events.jsonl",
+                    Local::now().format("%Y%m%dT%H%M%S")
+                ))
+                .await?;
+            }
+            if let (Some((_, writer)), false) = (&mut self.current, skipped) {
+                let mut line = serde_json::to_string(salvage_event)?;
+                line.push('\n');
+                writer.write_all(line.as_bytes()).await?;
+                // flush every line, surviving a crash is the entire point of this sink
+                writer.flush().await?;
+            }
+            if let SalvageEvent::EndShiftEvent { .. } = salvage_event {
+                self.close().await?;
+                self.shift_started = None;
+            }
+            Ok(())
+        }
+
+        async fn open(&mut self, file_name: String) -> std::io::Result<()> {
+            let path = self.archive_dir.join(file_name);
+            self.open_path(path).await
+        }
+
+        async fn open_path(&mut self, path: PathBuf) -> std::io::Result<()> {
+            debug!("archiving events to {path:?}");
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            self.current = Some((path, BufWriter::new(file)));
+            Ok(())
+        }
+
+        /// Flush and close the current file, returning where it was.
+        async fn close(&mut self) -> std::io::Result<Option<PathBuf>> {
+            match self.current.take() {
+                Some((path, mut writer)) => {
+                    writer.shutdown().await?;
+                    Ok(Some(path))
                 }
+                None => Ok(None),
+            }
+        }
+    }
+
+    /// Same naming scheme as the mod's own `_ledger.csv`/`_summary.txt` files, so they sort next to each other.
+    pub fn archive_file_name(shift_started: DateTime<Utc>, race_version: Option<i64>) -> String {
+        let race_prefix = race_version
+            .map(|version| format!("RACE{}-", version + 1))
+            .unwrap_or_default();
+        format!(
+            "{race_prefix}{}_events.jsonl",
+            shift_started.with_timezone(&Local).format("%Y%m%dT%H%M%S")
+        )
+    }
+
+    #[test]
+    fn test_archive_file_name() {
+        let shift_started = DateTime::parse_from_rfc3339("2021-07-04T12:34:56Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let local = shift_started
+            .with_timezone(&Local)
+            .format("%Y%m%dT%H%M%S")
+            .to_string();
+        assert_eq!(
+            archive_file_name(shift_started, None),
+            format!("{local}_events.jsonl")
+        );
+        assert_eq!(
+            archive_file_name(shift_started, Some(4)),
+            format!("RACE5-{local}_events.jsonl")
+        );
+    }
+
+    /// Keep every shift and everything salvaged in it in the database.
+    #[tracing::instrument]
+    pub async fn database_sink(
+        mut ledger_events_receiver: LedgerEventsReceiver,
+        database: Database,
+    ) {
+        // id of the shift we're in, if we're in one
+        let mut shift_id = None;
+        while let Some(Envelope {
+            event: salvage_event,
+            ..
+        }) = ledger_events_receiver.recv().await
+        {
+            let database = database.clone();
+            let record_result = tokio::task::spawn_blocking(move || {
+                database
+                    .record(shift_id, &salvage_event)
+                    .map_err(|e| error!("failed storing {salvage_event:?} in the database: {e}"))
+            })
+            .await;
+            match record_result {
+                Ok(Ok(new_shift_id)) => shift_id = new_shift_id,
+                // already logged
+                Ok(Err(())) => {}
+                Err(e) => error!("database sink task failed: {e}"),
             }
         }
     }
 
+    /// Once a RACE we've run before starts, race it against the best of those runs (the "ghost") and add how we're
+    /// doing to the stream, for speedrun style "ahead by 12000" overlays.
+    #[tracing::instrument(skip(ledger_events_sender))]
+    pub async fn ghost_sink(
+        mut ledger_events_receiver: LedgerEventsReceiver,
+        database: Database,
+        ledger_events_sender: WeakLedgerEventsSender,
+    ) {
+        let mut in_shift = false;
+        let mut race: Option<GhostRace> = None;
+        while let Some(Envelope {
+            event: salvage_event,
+            ..
+        }) = ledger_events_receiver.recv().await
+        {
+            match &salvage_event {
+                SalvageEvent::StartShiftEvent { .. } => {
+                    in_shift = true;
+                    race = None;
+                }
+                SalvageEvent::EndShiftEvent { .. }
+                | SalvageEvent::UpstreamDisconnectedEvent { .. } => {
+                    in_shift = false;
+                    race = None;
+                }
+                SalvageEvent::SetRACEInfoEvent { seed, version, .. } if in_shift => {
+                    let (seed, version) = (*seed, *version);
+                    let database = database.clone();
+                    match tokio::task::spawn_blocking(move || database.personal_best(seed, version))
+                        .await
+                    {
+                        Ok(Ok(Some(best))) => {
+                            let ghost = Ghost::new(&best);
+                            info!(
+                                "racing against shift {} ({:.0} salvaged), the best run of this RACE so far",
+                                ghost.shift_id,
+                                ghost.final_value()
+                            );
+                            race = Some(GhostRace::new(ghost));
+                        }
+                        Ok(Ok(None)) => {
+                            debug!("first run of RACE {seed}/{version}, no ghost to race")
+                        }
+                        Ok(Err(e)) => {
+                            error!("couldn't look up the best run of RACE {seed}/{version}: {e}")
+                        }
+                        Err(e) => error!("ghost sink task failed: {e}"),
+                    }
+                }
+                _ => {}
+            }
+            let Some(race) = &mut race else {
+                continue;
+            };
+            let derived = race.record(&salvage_event);
+            if derived.is_empty() {
+                continue;
+            }
+            // nobody left to send for means we're shutting down, and the stream is about to end anyways
+            let Some(ledger_events_sender) = ledger_events_sender.upgrade() else {
+                continue;
+            };
+            for salvage_event in derived {
+                ledger_events_sender.send_derived(salvage_event);
+            }
+        }
+    }
+
+    /// Keep the /metrics up to date.
+    #[tracing::instrument]
+    pub async fn metrics_sink(mut ledger_events_receiver: LedgerEventsReceiver, metrics: Metrics) {
+        while let Some(Envelope {
+            event: salvage_event,
+            ..
+        }) = ledger_events_receiver.recv().await
+        {
+            metrics.record(&salvage_event);
+        }
+    }
+
     /// Update the `State` struct so that clients asking for it later can have the most up-to-date state!
     #[tracing::instrument]
     pub async fn state_updater_sink(
-        mut ledger_events_receiver: Receiver<SalvageEvent>,
+        mut ledger_events_receiver: LedgerEventsReceiver,
         state: State,
     ) {
-        loop {
-            let recv_result = ledger_events_receiver.recv().await;
-            match recv_result {
-                Ok(salvage_event) => match salvage_event {
-                    SalvageEvent::StartShiftEvent { .. } => {
-                        debug!("startshift event received, updating state");
-                        let mut state = state.write().await;
-                        state.in_shift = true;
-                        debug!("startshift event done updating state");
-                    }
-                    SalvageEvent::EndShiftEvent { .. } => {
-                        debug!("endshift event received, updating state");
-                        let mut state = state.write().await;
-                        state.in_shift = false;
-                        debug!("endshift event done updating state");
-                    }
-                    _ => {}
-                },
-                Err(RecvError::Lagged(lagged_messages)) => {
-                    error!("status updater sink missed {lagged_messages} messages :(")
+        while let Some(Envelope {
+            event: salvage_event,
+            ..
+        }) = ledger_events_receiver.recv().await
+        {
+            match &salvage_event {
+                SalvageEvent::StartShiftEvent { .. } => {
+                    debug!("startshift event received, updating state");
+                    state.write().await.in_shift = true;
+                    debug!("startshift event done updating state");
+                }
+                SalvageEvent::EndShiftEvent { exit_cause, .. } => {
+                    debug!("endshift event received, updating state");
+                    let mut state = state.write().await;
+                    state.in_shift = false;
+                    state.last_exit_cause = exit_cause.clone();
+                    debug!("endshift event done updating state");
                 }
-                Err(RecvError::Closed) => {
-                    error!("somehow the status updater sink got a RecvError::Closed, this is a problem if it happened when not shutting down the game, bug sariya about it");
+                SalvageEvent::UpstreamConnectedEvent { .. } => {
+                    state.write().await.upstream_connected = true;
                 }
+                SalvageEvent::WelcomeEvent { .. } => {
+                    state.write().await.upstream = UpstreamInfo::from_welcome(&salvage_event);
+                }
+                SalvageEvent::UpstreamDisconnectedEvent { .. } => {
+                    // if the mod went away mid-shift we can't know if the shift is still going, so assume it isn't
+                    let mut state = state.write().await;
+                    state.upstream_connected = false;
+                    state.upstream = None;
+                    state.in_shift = false;
+                }
+                _ => {}
             }
         }
     }
 }
 
+/// Open --database, or bow out the way clap does if it can't be: that's a bad argument, not a bug.
+fn open_database(path: &Path) -> storage::Database {
+    storage::Database::open(path).unwrap_or_else(|e| {
+        Opts::command()
+            .error(
+                ErrorKind::Io,
+                format!("couldn't open database {path:?}: {e}"),
+            )
+            .exit()
+    })
+}
+
 #[tokio::main]
 pub async fn main() {
-    let opts = Arc::new(Opts::parse());
+    let matches = Opts::command().get_matches();
+    let mut opts = Opts::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(path) = opts.config.clone() {
+        match config::Config::load(&path) {
+            Ok(config) => config.apply(&mut opts, &matches),
+            Err(e) => Opts::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("couldn't read config {path:?}: {e}"),
+                )
+                .exit(),
+        }
+    }
+    let opts = Arc::new(opts);
     let max_level = match opts.verbose {
         0 => Level::ERROR,
         1 => Level::INFO,
@@ -299,29 +1356,28 @@ pub async fn main() {
         3 => Level::TRACE,
         _ => Level::TRACE,
     };
-    // TODO(sariya) this could probably stand to be better but i don't really care right this second
-    match opts.log_format.to_lowercase().as_str() {
-        "full" => {
+    match opts.log_format {
+        LogFormat::Full => {
             tracing_subscriber::fmt()
                 .with_max_level(max_level)
                 .with_thread_names(true)
                 .init();
         }
-        "compact" => {
+        LogFormat::Compact => {
             tracing_subscriber::fmt()
                 .with_max_level(max_level)
                 .with_thread_names(true)
                 .compact()
                 .init();
         }
-        "pretty" => {
+        LogFormat::Pretty => {
             tracing_subscriber::fmt()
                 .with_max_level(max_level)
                 .with_thread_names(true)
                 .pretty()
                 .init();
         }
-        "pretty_and_all_spans" => {
+        LogFormat::PrettyAndAllSpans => {
             tracing_subscriber::fmt()
                 .with_max_level(max_level)
                 .with_thread_ids(true)
@@ -330,141 +1386,293 @@ pub async fn main() {
                 .pretty()
                 .init();
         }
-        _ => {
-            panic!("you gave an invalid logging format and now i must die");
-        }
     };
 
     if opts.nocolorize {
         colored::control::set_override(false);
     }
+
+    // the mock mod, importing and loss reports are whole different programs really, they just live here to share code
+    let serve = match &opts.command {
+        Command::Serve(serve) => serve,
+        Command::MockMod {
+            port,
+            script,
+            speed,
+            seed,
+            items,
+            shift_seconds,
+            race,
+        } => {
+            let salvage_events = match script {
+                Some(script) => replay::read_event_log(script).await.unwrap_or_else(|e| {
+                    Opts::command()
+                        .error(
+                            ErrorKind::Io,
+                            format!("couldn't read script {script:?}: {e}"),
+                        )
+                        .exit()
+                }),
+                None => mock_mod::random_shift(&mock_mod::RandomShift {
+                    seed: *seed,
+                    items: *items,
+                    shift_seconds: *shift_seconds,
+                    race: *race,
+                }),
+            };
+            mock_mod::serve(*port, salvage_events, *speed).await;
+            return;
+        }
+        Command::Import { paths } => {
+            let paths = paths.clone();
+            let database = opts.database.as_deref().map(open_database);
+            let archive_dir = opts.archive_dir.clone();
+            let failures = tokio::task::spawn_blocking(move || {
+                import::import(
+                    &paths,
+                    database.as_ref(),
+                    archive_dir.as_deref(),
+                    &mut std::io::stdout().lock(),
+                )
+            })
+            .await
+            .expect("somehow failed spawning the import (oops)");
+            if failures > 0 {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Command::Losses { shift, top, json } => {
+            let Some(path) = &opts.database else {
+                Opts::command()
+                    .error(
+                        ErrorKind::MissingRequiredArgument,
+                        "no --database to look for losses in",
+                    )
+                    .exit()
+            };
+            let database = open_database(path);
+            let (shift, top) = (*shift, *top);
+            let report = tokio::task::spawn_blocking(move || {
+                losses::LossReport::from_database(&database, shift, top)
+            })
+            .await
+            .expect("somehow failed spawning the loss report (oops)");
+            match report {
+                Ok(Some(report)) if *json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).expect("loss reports always serialize")
+                ),
+                Ok(Some(report)) => print!("{report}"),
+                Ok(None) => {
+                    error!("no shift {} in {:?}", shift.unwrap_or_default(), path);
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    error!("couldn't read losses from {:?}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+    };
+    let missing = |what: &str| -> ! {
+        Opts::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                format!("no {what}, give one on the command line or in --config"),
+            )
+            .exit()
+    };
+    let listen_port = serve
+        .listen_port()
+        .unwrap_or_else(|| missing("listen port"));
+    let listen_address = if opts.expose {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        opts.listen_address
+    };
     info!("starting up server");
-    info!(
-        "connect port: {}, listen port: {}, listen address: {}",
-        opts.connect_port,
-        opts.listen_port,
-        (if opts.expose { "0.0.0.0" } else { "127.0.0.1" })
-    );
+    info!("listen port: {listen_port}, listen address: {listen_address}");
 
     // State we'll need to share with our components later.
     let clients = Clients::default();
     let state = State::default();
+    let backlog = Backlog::new(RwLock::new(backlog::ShiftBacklog::new(opts.backlog_size)));
+    let metrics = metrics::Metrics::default();
+    let database = opts.database.as_deref().map(open_database);
 
-    // Single-use channel specifically for shutting down gracefully.
+    // Single-use channel for whatever's feeding us events to say it's done, i.e. the game closed.
     let (shutdown_tx, shutdown_rx) = oneshot::channel();
 
-    // Kick off the mod<->lamprey WS connection!
-    // (when referring to this connection, we should call this "mod websocket" for consistency...)
-    let (ledger_events_sender_original, _) = broadcast::channel(512);
-    let opts_clone = Arc::clone(&opts);
+    // Kick off whatever's feeding us events: usually the mod<->lamprey WS connection!
+    let ledger_events_sender_original = envelope::LedgerEventsSender::new(512);
     let ledger_events_sender = ledger_events_sender_original.clone();
-    let clients_clone = clients.clone();
-    tokio::spawn(async move {
-        let connect_destination =
-            format!("ws://localhost:{}/racers-ledger/", opts_clone.connect_port);
-        let (websocketstream, response) = connect_async(connect_destination.as_str())
-            .await
-            .unwrap_or_else(|_| panic!("Can't connect to {}", connect_destination));
-        info!("connected to server");
-        info!("response code: {}", response.status());
-        let (_, mut websocket_rx) = websocketstream.split();
-        loop {
-            let msg = websocket_rx.next().await;
-            if msg.is_none() {
-                continue;
-            };
-            let msg = msg
-                .unwrap()
-                .expect("ran into an error with the message somehow i guess");
-
-            trace!("received message {msg}");
-            match msg {
-                Message::Text(string) => {
-                    trace!("trying to convert msg to object...");
-                    let event: Result<SalvageEvent, serde_json::Error> =
-                        serde_json::from_str(string.as_str());
-                    if let Ok(salvage_event) = event {
-                        // if we ever make ALL of the sinks optional this unwrap isn't guaranteed to work so we'll
-                        // need to implement some kind of retry logic maybe
-                        ledger_events_sender.send(salvage_event).unwrap();
-                    }
-                }
-                Message::Ping(data) => {
-                    trace!("received ping! (data: {data:?})");
-                }
-                Message::Pong(data) => {
-                    trace!("received pong! (data: {data:?}");
-                }
-                Message::Binary(data) => {
-                    trace!("received binary data: {data:?}")
-                }
-                Message::Close(close_frame) => {
-                    trace!("received close!");
-                    // default code 1000 "normal closure"
-                    // see https://developer.mozilla.org/en-US/docs/Web/API/CloseEvent for meanings of codes
-                    let code = 1000_u16;
-                    let reason = "game closed! (probably)";
-                    if let Some(close_frame) = close_frame {
-                        // TODO(sariya) pass down the code/reason to consumers?
-                        trace!("close frame info: {close_frame:#?}");
-                    }
+    let current_shift = ledger_events_sender_original.current_shift();
+    let source_task = match serve {
+        ServeCommand::Connect {
+            connect_port,
+            connect_host,
+            connect_path,
+            max_reconnect_delay,
+            persist,
+            ..
+        } => {
+            let connect_port = connect_port.unwrap_or_else(|| missing("connect port"));
+            info!("connect port: {connect_port}");
+            tokio::spawn(upstream::mod_websocket_task(
+                format!("ws://{connect_host}:{connect_port}{connect_path}"),
+                Duration::from_secs(*max_reconnect_delay),
+                *persist,
+                ledger_events_sender,
+                shutdown_tx,
+            ))
+        }
+        ServeCommand::Replay {
+            file,
+            speed,
+            wait_for_client,
+            ..
+        } => {
+            info!("replaying {file:?} at {speed:?}");
+            tokio::spawn(replay::replay_task(
+                file.clone(),
+                *speed,
+                *wait_for_client,
+                ledger_events_sender,
+                clients.clone(),
+                shutdown_tx,
+            ))
+        }
+    };
 
-                    // server died, let's clean up and tell our clients and die too
-                    // TODO(sariya) this should probably be in the updater sink, but it
-                    // unfortunately needs info to data (the message::close frame)
-                    for tx in clients_clone.read().await.values() {
-                        if let Err(_disconnected) =
-                            tx.send(Ok(warp::ws::Message::close_with(code, reason)))
-                        {
-                            // the tx is disconnected and already gone
-                        }
-                    }
-                    // let's get the webserver shut down too, now!
-                    shutdown_tx
-                        .send(())
-                        .expect("somehow failed sending the shutdown signal lmao");
-                    break;
-                }
-                Message::Frame(data) => {
-                    trace!("I have no idea what happened now -- klaernie. Got data: {data:?}")
-                }
-            }
+    // Keep track of the sinks so that we can let them finish up when it's time to go.
+    let mut sink_tasks = vec![];
+
+    // Spawn a metrics sink to count everything for /metrics
+    let ledger_events_receiver =
+        ledger_events_sender_original.subscribe("metrics", metrics.clone());
+    let metrics_clone = metrics.clone();
+    sink_tasks.push(tokio::spawn(async move {
+        sinks::metrics_sink(ledger_events_receiver, metrics_clone).await
+    }));
+
+    // Spawn a console sink to log when we get new ledger events, unless we've been told not to
+    if !opts.noconsole {
+        let mut skip_types: HashSet<String> = opts.console_skip_types.iter().cloned().collect();
+        if opts.notime_tick {
+            skip_types.insert("timeTickEvent".to_string());
+            skip_types.insert("racePaceEvent".to_string());
+            skip_types.insert("ghostDeltaEvent".to_string());
         }
-    });
+        let ledger_events_receiver =
+            ledger_events_sender_original.subscribe("console", metrics.clone());
+        sink_tasks.push(tokio::spawn(async move {
+            sinks::console_sink(ledger_events_receiver, skip_types).await
+        }));
+    }
+
+    // Spawn an archive sink to write every event to disk, if we've been told where
+    if let Some(archive_dir) = opts.archive_dir.clone() {
+        let skip_types = opts.archive_skip_types.iter().cloned().collect();
+        let ledger_events_receiver =
+            ledger_events_sender_original.subscribe("json_lines_archive", metrics.clone());
+        sink_tasks.push(tokio::spawn(async move {
+            sinks::json_lines_archive_sink(ledger_events_receiver, archive_dir, skip_types).await
+        }));
+    }
 
-    // Spawn a console sink to log when we get new ledger events
-    let opts_clone = Arc::clone(&opts);
-    let ledger_events_receiver = ledger_events_sender_original.subscribe();
-    tokio::spawn(async move {
-        sinks::console_sink(ledger_events_receiver, !opts_clone.notime_tick).await
-    });
+    // Spawn a database sink to keep every shift around for later, if we've been told where
+    if let Some(database) = database.clone() {
+        let ledger_events_receiver =
+            ledger_events_sender_original.subscribe("database", metrics.clone());
+        sink_tasks.push(tokio::spawn(async move {
+            sinks::database_sink(ledger_events_receiver, database).await
+        }));
+    }
+
+    // Keep the archive and database from growing forever, if we've been told how long to keep things
+    let retention = retention::Retention {
+        archive_days: opts.archive_retention_days,
+        database_days: opts.database_retention_days,
+    };
+    if retention.archive_days.is_some() || retention.database_days.is_some() {
+        tokio::spawn(retention::retention_task(
+            retention,
+            opts.archive_dir.clone(),
+            database.clone(),
+        ));
+    }
+
+    // Spawn a ghost sink to race RACEs against their best run so far, if we have a history of them
+    if let Some(database) = database.clone() {
+        let ledger_events_receiver =
+            ledger_events_sender_original.subscribe("ghost", metrics.clone());
+        let ledger_events_sender = ledger_events_sender_original.downgrade();
+        sink_tasks.push(tokio::spawn(async move {
+            sinks::ghost_sink(ledger_events_receiver, database, ledger_events_sender).await
+        }));
+    }
 
     // Spawn a state updater sink to keep abreast of when the game state changes
-    let ledger_events_receiver = ledger_events_sender_original.subscribe();
+    let ledger_events_receiver =
+        ledger_events_sender_original.subscribe("state_updater", metrics.clone());
     let state_clone = state.clone();
-    tokio::spawn(
-        async move { sinks::state_updater_sink(ledger_events_receiver, state_clone).await },
-    );
+    sink_tasks.push(tokio::spawn(async move {
+        sinks::state_updater_sink(ledger_events_receiver, state_clone).await
+    }));
 
     // Spawn a sink for sending all of our proxy clients the ledger events!
-    let ledger_events_receiver = ledger_events_sender_original.subscribe();
+    let ledger_events_receiver =
+        ledger_events_sender_original.subscribe("websocket_client_updater", metrics.clone());
     let clients_clone = clients.clone();
-    tokio::spawn(async move {
-        sinks::websocket_client_updater_sink(ledger_events_receiver, clients_clone).await
-    });
+    let backlog_clone = backlog.clone();
+    sink_tasks.push(tokio::spawn(async move {
+        sinks::websocket_client_updater_sink(ledger_events_receiver, clients_clone, backlog_clone)
+            .await
+    }));
 
     // let's actually serve our API to the world (or, at least localhost) now!
-    let server = warp::serve(filters::api(state.clone(), clients.clone()));
-    let bind_address = if opts.expose {
-        [0, 0, 0, 0]
-    } else {
-        [127, 0, 0, 1]
-    };
+    let server = warp::serve(filters::api(
+        state.clone(),
+        current_shift,
+        clients.clone(),
+        backlog.clone(),
+        database,
+        metrics,
+        client_queue::QueueOptions {
+            capacity: opts.client_queue_size,
+            policy: opts.slow_client_policy,
+        },
+    ));
+    let (server_shutdown_tx, server_shutdown_rx) = oneshot::channel::<()>();
     let (_, server) =
-        server.bind_with_graceful_shutdown((bind_address, opts.listen_port), async move {
-            shutdown_rx.await.ok();
+        server.bind_with_graceful_shutdown((listen_address, listen_port), async move {
+            server_shutdown_rx.await.ok();
         });
-    tokio::spawn(server)
+    let server = tokio::spawn(server);
+
+    let shutdown = tokio::select! {
+        shutdown = shutdown_rx => shutdown.unwrap_or_else(|_| {
+            error!("lost whatever was feeding us events, shutting down");
+            shutdown::Shutdown::new(ShutdownCause::Crashed, 1011, "lamprey lost the mod")
+        }),
+        signal = shutdown::signal() => {
+            info!("got {signal}, shutting down");
+            shutdown::Shutdown::new(ShutdownCause::Stopped, 1001, "lamprey shutting down")
+        }
+    };
+    shutdown::wind_down(
+        source_task,
+        ledger_events_sender_original,
+        sink_tasks,
+        &clients,
+        &shutdown,
+    )
+    .await;
+    // let's get the webserver shut down too, now!
+    server_shutdown_tx.send(()).ok();
+    server
         .await
         .expect("somehow failed spawning the server (oops)")
 }
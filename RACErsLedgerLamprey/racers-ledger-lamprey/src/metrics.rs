@@ -0,0 +1,279 @@
+use std::sync::{atomic::AtomicU64, Arc};
+
+use prometheus_client::{
+    encoding::{text::encode, EncodeLabelSet},
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
+    registry::Registry,
+};
+
+use racers_ledger_datatypes::SalvageEvent;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct EventLabels {
+    r#type: String,
+}
+
+/// Whether we got paid for it (`salvaged`) or not (`destroyed`).
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct OutcomeLabels {
+    outcome: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct SalvagedByLabels {
+    salvaged_by: String,
+    outcome: &'static str,
+}
+
+/// `complete`, `abort` or `unknown`, see `ExitCause::kind`. `none` if the mod didn't say.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ExitCauseLabels {
+    exit_cause: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct SinkLabels {
+    sink: &'static str,
+}
+
+fn outcome(destroyed: bool) -> OutcomeLabels {
+    OutcomeLabels {
+        outcome: if destroyed { "destroyed" } else { "salvaged" },
+    }
+}
+
+/// Prometheus metrics about what's been salvaged and how the lamprey is holding up, for /metrics.
+///
+/// Cheap to clone, every clone updates the same metrics.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+    events: Family<EventLabels, Counter>,
+    unknown_events: Counter,
+    shift_value: Family<OutcomeLabels, Gauge<f64, AtomicU64>>,
+    shift_mass: Family<OutcomeLabels, Gauge<f64, AtomicU64>>,
+    shift_items: Family<OutcomeLabels, Gauge>,
+    value: Family<OutcomeLabels, Counter<f64, AtomicU64>>,
+    items: Family<SalvagedByLabels, Counter>,
+    shifts_ended: Family<ExitCauseLabels, Counter>,
+    in_shift: Gauge,
+    upstream_connected: Gauge,
+    proxy_clients: Gauge,
+    lagged: Family<SinkLabels, Counter>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let mut registry = Registry::with_prefix("racers_ledger");
+        let metrics = Metrics {
+            registry: Arc::default(),
+            events: Family::default(),
+            unknown_events: Counter::default(),
+            shift_value: Family::default(),
+            shift_mass: Family::default(),
+            shift_items: Family::default(),
+            value: Family::default(),
+            items: Family::default(),
+            shifts_ended: Family::default(),
+            in_shift: Gauge::default(),
+            upstream_connected: Gauge::default(),
+            proxy_clients: Gauge::default(),
+            lagged: Family::default(),
+        };
+        registry.register(
+            "events",
+            "Events the lamprey has heard about, by type",
+            metrics.events.clone(),
+        );
+        registry.register(
+            "unknown_events",
+            "Events the lamprey didn't recognize and passed along as-is (also counted in events)",
+            metrics.unknown_events.clone(),
+        );
+        registry.register(
+            "shift_value",
+            "Value salvaged or destroyed in the current (or last) shift",
+            metrics.shift_value.clone(),
+        );
+        registry.register(
+            "shift_mass",
+            "Mass salvaged or destroyed in the current (or last) shift",
+            metrics.shift_mass.clone(),
+        );
+        registry.register(
+            "shift_items",
+            "Items salvaged or destroyed in the current (or last) shift",
+            metrics.shift_items.clone(),
+        );
+        registry.register(
+            "value",
+            "Value salvaged or destroyed, over every shift",
+            metrics.value.clone(),
+        );
+        registry.register(
+            "items",
+            "Items salvaged or destroyed over every shift, by what salvaged them",
+            metrics.items.clone(),
+        );
+        registry.register(
+            "shifts_ended",
+            "Shifts that ended, by why they ended",
+            metrics.shifts_ended.clone(),
+        );
+        registry.register(
+            "in_shift",
+            "1 if we're in a shift right now",
+            metrics.in_shift.clone(),
+        );
+        registry.register(
+            "upstream_connected",
+            "1 if we can hear the mod right now",
+            metrics.upstream_connected.clone(),
+        );
+        registry.register(
+            "proxy_clients",
+            "Proxy clients (websocket and SSE) connected right now",
+            metrics.proxy_clients.clone(),
+        );
+        registry.register(
+            "sink_lagged_messages",
+            "Events a sink couldn't keep up with and missed",
+            metrics.lagged.clone(),
+        );
+        Metrics {
+            registry: Arc::new(registry),
+            ..metrics
+        }
+    }
+}
+
+impl Metrics {
+    /// Count one event.
+    pub fn record(&self, salvage_event: &SalvageEvent) {
+        self.events
+            .get_or_create(&EventLabels {
+                r#type: salvage_event.event_type().to_string(),
+            })
+            .inc();
+        match salvage_event {
+            SalvageEvent::StartShiftEvent { .. } => {
+                self.in_shift.set(1);
+                for destroyed in [false, true] {
+                    self.shift_value.get_or_create(&outcome(destroyed)).set(0.0);
+                    self.shift_mass.get_or_create(&outcome(destroyed)).set(0.0);
+                    self.shift_items.get_or_create(&outcome(destroyed)).set(0);
+                }
+            }
+            SalvageEvent::EndShiftEvent { exit_cause, .. } => {
+                self.in_shift.set(0);
+                self.shifts_ended
+                    .get_or_create(&ExitCauseLabels {
+                        exit_cause: exit_cause
+                            .as_ref()
+                            .map_or("none", |exit_cause| exit_cause.kind()),
+                    })
+                    .inc();
+            }
+            SalvageEvent::ShiftSalvageLogEntry {
+                mass,
+                salvaged_by,
+                value,
+                destroyed,
+                ..
+            } => {
+                let labels = outcome(*destroyed);
+                self.shift_value.get_or_create(&labels).inc_by(*value);
+                self.shift_mass.get_or_create(&labels).inc_by(*mass);
+                self.shift_items.get_or_create(&labels).inc();
+                self.value.get_or_create(&labels).inc_by(*value);
+                self.items
+                    .get_or_create(&SalvagedByLabels {
+                        salvaged_by: salvaged_by.clone(),
+                        outcome: labels.outcome,
+                    })
+                    .inc();
+            }
+            SalvageEvent::Unknown(_) => {
+                self.unknown_events.inc();
+            }
+            SalvageEvent::UpstreamConnectedEvent { .. } => {
+                self.upstream_connected.set(1);
+            }
+            SalvageEvent::UpstreamDisconnectedEvent { .. } => {
+                // same as the state updater sink: no mod, no shift
+                self.upstream_connected.set(0);
+                self.in_shift.set(0);
+            }
+            _ => {}
+        }
+    }
+
+    /// A sink's broadcast receiver lagged and missed `missed` events.
+    pub fn lagged(&self, sink: &'static str, missed: u64) {
+        self.lagged
+            .get_or_create(&SinkLabels { sink })
+            .inc_by(missed);
+    }
+
+    /// Everything, in the OpenMetrics text format.
+    pub fn encode(&self, proxy_clients: usize) -> String {
+        self.proxy_clients.set(proxy_clients as i64);
+        let mut body = String::new();
+        encode(&mut body, &self.registry).expect("writing to a String can't fail");
+        body
+    }
+}
+
+#[test]
+fn test_metrics_count_salvage() {
+    let now = chrono::Utc::now();
+    let entry = |salvaged_by: &str, value, destroyed| SalvageEvent::ShiftSalvageLogEntry {
+        object_name: "Thing".into(),
+        mass: 2.0,
+        categories: vec![],
+        salvaged_by: salvaged_by.into(),
+        value,
+        mass_based_value: false,
+        destroyed,
+        game_time: 1.0,
+        system_time: now,
+    };
+    let metrics = Metrics::default();
+    metrics.record(&entry("Furnace", 999.0, false));
+    metrics.record(&SalvageEvent::StartShiftEvent { system_time: now });
+    metrics.record(&entry("Furnace", 100.0, false));
+    metrics.record(&entry("Furnace", 50.0, true));
+    metrics.record(&entry("Processor", 25.0, false));
+    metrics.lagged("console", 3);
+    metrics.record(&serde_json::from_str(r#"{"type":"shinyNewEvent"}"#).unwrap());
+
+    let body = metrics.encode(2);
+    for line in [
+        r#"racers_ledger_events_total{type="shiftSalvageLogEntry"} 4"#,
+        r#"racers_ledger_shift_value{outcome="salvaged"} 125.0"#,
+        r#"racers_ledger_shift_value{outcome="destroyed"} 50.0"#,
+        r#"racers_ledger_value_total{outcome="salvaged"} 1124.0"#,
+        r#"racers_ledger_items_total{salvaged_by="Furnace",outcome="salvaged"} 2"#,
+        r#"racers_ledger_in_shift 1"#,
+        r#"racers_ledger_proxy_clients 2"#,
+        r#"racers_ledger_sink_lagged_messages_total{sink="console"} 3"#,
+        r#"racers_ledger_events_total{type="shinyNewEvent"} 1"#,
+        r#"racers_ledger_unknown_events_total 1"#,
+    ] {
+        assert!(body.lines().any(|l| l == line), "{} not in\n{}", line, body);
+    }
+
+    metrics.record(
+        &serde_json::from_str(
+            r#"{"type":"endShiftEvent","exitCause":"unknown (gameover -> loadinginprogress)","systemTime":"2021-07-04T12:34:56Z"}"#,
+        )
+        .unwrap(),
+    );
+    let body = metrics.encode(2);
+    for line in [
+        r#"racers_ledger_shifts_ended_total{exit_cause="unknown"} 1"#,
+        r#"racers_ledger_in_shift 0"#,
+    ] {
+        assert!(body.lines().any(|l| l == line), "{} not in\n{}", line, body);
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures::{FutureExt, StreamExt};
+use log::{debug, error, info};
+use rand::{rngs::StdRng, seq::IndexedRandom, Rng, SeedableRng};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use warp::{
+    ws::{Message, WebSocket},
+    Filter,
+};
+
+use racers_ledger_datatypes::{ExitCause, SalvageEvent, MOD_EVENT_TYPES, PROTOCOL_VERSION};
+
+use super::replay::{play_events, ReplaySpeed};
+
+/// Everyone connected to the mock mod, same deal as `Clients` but for the other side of the lamprey.
+/// (plain Mutex since broadcasting happens from a sync callback)
+type Sessions = Arc<Mutex<HashMap<usize, mpsc::UnboundedSender<Result<Message, warp::Error>>>>>;
+
+/// session id counter, key for Sessions
+static NEXT_SESSION_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// What to generate a random shift out of.
+#[derive(Debug)]
+pub struct RandomShift {
+    /// Makes the shift reproducible. Random if not set.
+    pub seed: Option<u64>,
+    /// How many things get salvaged or destroyed.
+    pub items: usize,
+    /// In-game length of the shift, in seconds.
+    pub shift_seconds: NonZeroU32,
+    /// Pretend it's a RACE, with RACE info.
+    pub race: bool,
+}
+
+const OBJECT_NAMES: &[&str] = &[
+    "Hull Plate",
+    "Reactor",
+    "Thruster",
+    "Fuel Tank",
+    "Computer",
+    "Seat",
+    "Coolant Tank",
+    "Airlock",
+    "Power Cell",
+    "Cargo Container",
+];
+const CATEGORIES: &[&str] = &[
+    "Nanocarbon",
+    "Aluminum",
+    "Ferrous",
+    "Salvage",
+    "Reactor",
+    "Thruster",
+    "Electronics",
+];
+const SALVAGED_BY: &[&str] = &["Furnace", "Processor", "Barge", "PickUp"];
+
+/// Make up a plausible shift from start to finish, laid out in (system) time from now on.
+pub fn random_shift(options: &RandomShift) -> Vec<SalvageEvent> {
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    let shift_start = Utc::now();
+    let at = |game_time: f64| -> DateTime<Utc> {
+        shift_start + chrono::Duration::milliseconds((game_time * 1000.0) as i64)
+    };
+    let max_time = f64::from(options.shift_seconds.get());
+
+    let mut salvage_times: Vec<f64> = (0..options.items)
+        .map(|_| rng.random_range(0.0..max_time))
+        .collect();
+    salvage_times.sort_by(|a, b| a.total_cmp(b));
+    let mut salvage_times = salvage_times.into_iter().peekable();
+
+    let mut salvage_events = vec![
+        SalvageEvent::GameStateChangedEvent {
+            current_game_state: "gameplay".into(),
+            previous_game_state: "loadinginprogress".into(),
+            system_time: at(0.0),
+        },
+        SalvageEvent::StartShiftEvent {
+            system_time: at(0.0),
+        },
+    ];
+    if options.race {
+        salvage_events.push(SalvageEvent::SetRACEInfoEvent {
+            seed: rng.random_range(0..i32::MAX as i64),
+            version: rng.random_range(0..52),
+            start_date_utc: shift_start.format("%Y-%m-%dT00:00:00Z").to_string(),
+            max_total_value: rng.random_range(1_000_000..5_000_000),
+            max_salvage_mass: rng.random_range(100_000..500_000),
+            system_time: at(0.0),
+        });
+    }
+    for second in 1..=options.shift_seconds.get() {
+        let current_time = second as f64;
+        while let Some(game_time) = salvage_times.next_if(|game_time| *game_time < current_time) {
+            let mass_based_value = rng.random_bool(0.5);
+            let category_count = rng.random_range(1..=2);
+            let categories = CATEGORIES
+                .choose_multiple(&mut rng, category_count)
+                .map(|category| category.to_string())
+                .collect();
+            salvage_events.push(SalvageEvent::ShiftSalvageLogEntry {
+                object_name: OBJECT_NAMES.choose(&mut rng).unwrap().to_string(),
+                mass: (rng.random_range(10.0..5000.0_f64) * 1000.0).round() / 1000.0,
+                categories,
+                salvaged_by: SALVAGED_BY.choose(&mut rng).unwrap().to_string(),
+                value: (rng.random_range(10.0..20_000.0_f64) * 100.0).round() / 100.0,
+                mass_based_value,
+                destroyed: rng.random_bool(0.15),
+                game_time: game_time as f32,
+                system_time: at(game_time),
+            });
+        }
+        salvage_events.push(SalvageEvent::TimeTickEvent {
+            current_time,
+            max_time,
+            system_time: at(current_time),
+        });
+    }
+    salvage_events.extend([
+        SalvageEvent::GameStateChangedEvent {
+            current_game_state: "gamecomplete".into(),
+            previous_game_state: "gameplay".into(),
+            system_time: at(max_time),
+        },
+        SalvageEvent::EndShiftEvent {
+            exit_cause: Some(ExitCause::Complete),
+            system_time: at(max_time),
+        },
+        SalvageEvent::GameStateChangedEvent {
+            current_game_state: "loadinginprogress".into(),
+            previous_game_state: "gamecomplete".into(),
+            system_time: at(max_time),
+        },
+    ]);
+    salvage_events
+}
+
+/// Serve `ws://localhost:<port>/racers-ledger/` like the mod's `EventBroadcastServer` does: greet everyone who connects,
+/// broadcast `salvage_events` once the first client shows up, then close everything like `LampreyManager.Stop()`.
+#[tracing::instrument(skip(salvage_events))]
+pub async fn serve(port: u16, salvage_events: Vec<SalvageEvent>, speed: ReplaySpeed) {
+    let sessions = Sessions::default();
+    let sessions_clone = sessions.clone();
+    let route = warp::path("racers-ledger")
+        .and(warp::path::end())
+        .and(warp::ws())
+        .map(move |ws: warp::ws::Ws| {
+            let sessions = sessions_clone.clone();
+            ws.on_upgrade(move |socket| session_connected(socket, sessions))
+        });
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let (address, server) =
+        warp::serve(route).bind_with_graceful_shutdown(([127, 0, 0, 1], port), async move {
+            shutdown_rx.await.ok();
+        });
+    let server = tokio::spawn(server);
+    info!("mock mod listening on ws://{address}/racers-ledger/");
+
+    while sessions.lock().unwrap().is_empty() {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    info!("got a client, starting the shift");
+    play_events(salvage_events, speed, |salvage_event| {
+        let json =
+            serde_json::to_string(&salvage_event).expect("salvage events should always serialize");
+        broadcast(&sessions, Message::text(json));
+    })
+    .await;
+
+    // the real game never quits the instant a shift ends, give everyone a moment to catch up
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    info!("shift done, closing up shop");
+    broadcast(
+        &sessions,
+        Message::close_with(1000_u16, "lamprey server closing"),
+    );
+    sessions.lock().unwrap().clear();
+    shutdown_tx.send(()).ok();
+    server.await.ok();
+}
+
+fn broadcast(sessions: &Sessions, message: Message) {
+    for tx in sessions.lock().unwrap().values() {
+        if let Err(_disconnected) = tx.send(Ok(message.clone())) {
+            // the session is gone already
+        }
+    }
+}
+
+async fn session_connected(websocket: WebSocket, sessions: Sessions) {
+    let my_id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+    let (session_ws_tx, mut session_ws_rx) = websocket.split();
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::task::spawn(
+        UnboundedReceiverStream::new(rx)
+            .forward(session_ws_tx)
+            .map(|result| {
+                if let Err(e) = result {
+                    error!("mock mod websocket send error: {e}");
+                }
+            }),
+    );
+    info!("new client connected (session {my_id})");
+    let welcome = SalvageEvent::WelcomeEvent {
+        msg: "hello new client!".into(),
+        protocol_version: Some(PROTOCOL_VERSION),
+        mod_version: Some("mock".into()),
+        game_build: None,
+        supported_event_types: Some(MOD_EVENT_TYPES.iter().map(|t| t.to_string()).collect()),
+        system_time: Some(Utc::now()),
+    };
+    tx.send(Ok(Message::text(
+        serde_json::to_string(&welcome).expect("welcome event should always serialize"),
+    )))
+    .ok();
+    sessions.lock().unwrap().insert(my_id, tx);
+    while let Some(result) = session_ws_rx.next().await {
+        if let Err(e) = result {
+            error!("(session {my_id}) {e}");
+            break;
+        }
+    }
+    debug!("session {my_id} went away");
+    sessions.lock().unwrap().remove(&my_id);
+}
+
+#[test]
+fn test_random_shift_is_a_whole_shift() {
+    let salvage_events = random_shift(&RandomShift {
+        seed: Some(42),
+        items: 20,
+        shift_seconds: NonZeroU32::new(30).unwrap(),
+        race: true,
+    });
+    assert!(matches!(
+        salvage_events.get(1),
+        Some(SalvageEvent::StartShiftEvent { .. })
+    ));
+    assert!(matches!(
+        salvage_events.get(2),
+        Some(SalvageEvent::SetRACEInfoEvent { .. })
+    ));
+    assert!(matches!(
+        salvage_events.iter().rev().nth(1),
+        Some(SalvageEvent::EndShiftEvent { .. })
+    ));
+    let entries = salvage_events
+        .iter()
+        .filter(|e| matches!(e, SalvageEvent::ShiftSalvageLogEntry { .. }))
+        .count();
+    let ticks = salvage_events
+        .iter()
+        .filter(|e| matches!(e, SalvageEvent::TimeTickEvent { .. }))
+        .count();
+    assert_eq!(entries, 20);
+    assert_eq!(ticks, 30);
+    let times: Vec<_> = salvage_events
+        .iter()
+        .filter_map(|e| e.system_time())
+        .collect();
+    assert!(
+        times.windows(2).all(|pair| pair[0] <= pair[1]),
+        "events should be in order"
+    );
+}
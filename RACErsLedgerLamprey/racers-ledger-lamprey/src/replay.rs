@@ -0,0 +1,192 @@
+use std::{path::PathBuf, str::FromStr, time::Duration};
+
+use log::{error, info, warn};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, BufReader},
+    sync::oneshot,
+};
+
+use racers_ledger_datatypes::{SalvageEvent, ShutdownCause};
+
+use super::envelope::LedgerEventsSender;
+use super::shutdown::Shutdown;
+use super::Clients;
+
+/// How fast to play a recording back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Keep the original spacing between events, divided by this much.
+    Multiplier(f64),
+    /// Don't wait between events at all.
+    Instant,
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = String;
+
+    /// Accepts `instant`, or a multiplier like `1`, `10` or `10x`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("instant") {
+            return Ok(ReplaySpeed::Instant);
+        }
+        let multiplier: f64 = s
+            .trim_end_matches(['x', 'X'])
+            .parse()
+            .map_err(|_| format!("{s:?} isn't `instant` or a speed multiplier like `10x`"))?;
+        if multiplier.is_finite() && multiplier > 0.0 {
+            Ok(ReplaySpeed::Multiplier(multiplier))
+        } else {
+            Err(format!(
+                "speed multiplier has to be more than zero, not {s:?}"
+            ))
+        }
+    }
+}
+
+impl ReplaySpeed {
+    /// How long to wait before sending an event that originally happened `original_gap` after the last one.
+    fn scale(&self, original_gap: chrono::Duration) -> Duration {
+        match self {
+            ReplaySpeed::Instant => Duration::ZERO,
+            ReplaySpeed::Multiplier(multiplier) => original_gap
+                .to_std()
+                .map(|gap| gap.div_f64(*multiplier))
+                // events out of order in the log just get sent right away
+                .unwrap_or(Duration::ZERO),
+        }
+    }
+}
+
+/// Plays back a JSON Lines event log (like the ones the archive sink writes) into the broadcast channel, pretending
+/// to be the mod, then asks for a shutdown same as when the game closes.
+#[tracing::instrument(skip(ledger_events_sender, clients, shutdown_tx))]
+pub async fn replay_task(
+    file: PathBuf,
+    speed: ReplaySpeed,
+    wait_for_client: bool,
+    ledger_events_sender: LedgerEventsSender,
+    clients: Clients,
+    shutdown_tx: oneshot::Sender<Shutdown>,
+) {
+    if wait_for_client {
+        info!("waiting for a proxy client to connect before starting the replay");
+        while clients.read().await.is_empty() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+    match read_event_log(&file).await {
+        Ok(salvage_events) => {
+            play_events(salvage_events, speed, |salvage_event| {
+                ledger_events_sender.send(salvage_event);
+            })
+            .await
+        }
+        Err(e) => error!("failed reading {file:?}: {e}"),
+    }
+    info!("replay of {file:?} finished");
+    shutdown_tx
+        .send(Shutdown::new(
+            ShutdownCause::ReplayFinished,
+            1000,
+            "replay finished",
+        ))
+        .expect("somehow failed sending the shutdown signal lmao");
+}
+
+/// Read a JSON Lines event log, skipping (and complaining about) lines that aren't JSON or are mod events that don't
+/// parse. Events of a `type` we don't know are kept, the lamprey passed those along when it archived them too.
+pub async fn read_event_log(file: &PathBuf) -> std::io::Result<Vec<SalvageEvent>> {
+    let mut lines = BufReader::new(File::open(file).await?).lines();
+    let mut line_number = 0;
+    let mut salvage_events = vec![];
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<SalvageEvent>(&line) {
+            Ok(salvage_event) => match salvage_event.malformed() {
+                Some(e) => warn!(
+                    "skipping line {line_number} of {file:?}, it's a malformed {}: {e}",
+                    salvage_event.event_type()
+                ),
+                None => salvage_events.push(salvage_event),
+            },
+            Err(e) => warn!("skipping line {line_number} of {file:?}, it isn't JSON: {e}"),
+        }
+    }
+    Ok(salvage_events)
+}
+
+/// Hand each event to `send`, waiting between them according to their original `system_time` spacing and `speed`.
+pub async fn play_events(
+    salvage_events: Vec<SalvageEvent>,
+    speed: ReplaySpeed,
+    mut send: impl FnMut(SalvageEvent),
+) {
+    let mut previous_time = None;
+    for salvage_event in salvage_events {
+        if let Some(system_time) = salvage_event.system_time() {
+            if let Some(previous_time) = previous_time {
+                let delay = speed.scale(system_time - previous_time);
+                // even a zero sleep waits for the next timer tick (a millisecond or so), which adds up, but still let
+                // everyone else have a go so we can't lap the sinks on a busy (or single core) machine
+                if delay.is_zero() {
+                    tokio::task::yield_now().await;
+                } else {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+            previous_time = Some(system_time);
+        }
+        send(salvage_event);
+    }
+}
+
+#[test]
+fn test_replay_speed_parsing() {
+    assert_eq!("instant".parse(), Ok(ReplaySpeed::Instant));
+    assert_eq!("1".parse(), Ok(ReplaySpeed::Multiplier(1.0)));
+    assert_eq!("10x".parse(), Ok(ReplaySpeed::Multiplier(10.0)));
+    assert_eq!("0.5X".parse(), Ok(ReplaySpeed::Multiplier(0.5)));
+    assert!("0".parse::<ReplaySpeed>().is_err());
+    assert!("fast".parse::<ReplaySpeed>().is_err());
+}
+
+#[test]
+fn test_replay_speed_scaling() {
+    let gap = chrono::Duration::seconds(10);
+    assert_eq!(
+        ReplaySpeed::Multiplier(10.0).scale(gap),
+        Duration::from_secs(1)
+    );
+    assert_eq!(ReplaySpeed::Instant.scale(gap), Duration::ZERO);
+    assert_eq!(
+        ReplaySpeed::Multiplier(1.0).scale(-gap),
+        Duration::ZERO,
+        "out-of-order events shouldn't wait"
+    );
+}
+
+#[tokio::test]
+async fn test_read_event_log_skips_what_it_cant_replay() {
+    let file = std::env::temp_dir().join(format!("lamprey-replay-{}.jsonl", std::process::id()));
+    std::fs::write(
+        &file,
+        "{\"type\":\"startShiftEvent\",\"systemTime\":\"2021-07-04T12:34:56Z\"}\n\
+         not json at all\n\
+         \n\
+         {\"type\":\"endShiftEvent\",\"exitCause\":42}\n\
+         {\"type\":\"shinyNewEvent\",\"systemTime\":\"2021-07-04T12:35:56Z\"}\n",
+    )
+    .unwrap();
+    let types: Vec<String> = read_event_log(&file)
+        .await
+        .unwrap()
+        .iter()
+        .map(|salvage_event| salvage_event.event_type().to_string())
+        .collect();
+    assert_eq!(types, ["startShiftEvent", "shinyNewEvent"]);
+    std::fs::remove_file(&file).ok();
+}
@@ -0,0 +1,124 @@
+use std::{
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use chrono::Utc;
+use log::{error, info};
+
+use super::storage::Database;
+
+/// How often to look for anything old enough to throw away.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+fn days(days: NonZeroU32) -> Duration {
+    Duration::from_secs(u64::from(days.get()) * 24 * 60 * 60)
+}
+
+/// How long archive files and stored shifts are kept around for. `None` is forever.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Retention {
+    pub archive_days: Option<NonZeroU32>,
+    pub database_days: Option<NonZeroU32>,
+}
+
+/// Delete the archive's `_events.jsonl` files that haven't been written to in `keep_days`, returning how many there
+/// were. Anything else in `archive_dir` is left alone. Blocking.
+pub fn prune_archive(archive_dir: &Path, keep_days: NonZeroU32) -> std::io::Result<usize> {
+    let Some(cutoff) = SystemTime::now().checked_sub(days(keep_days)) else {
+        return Ok(0);
+    };
+    let mut pruned = 0;
+    for entry in std::fs::read_dir(archive_dir)? {
+        let path = entry?.path();
+        let is_archive = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .is_some_and(|file_name| file_name.ends_with("_events.jsonl"));
+        if !is_archive {
+            continue;
+        }
+        // one file we can't get rid of shouldn't keep us from getting rid of the rest
+        match std::fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) if modified < cutoff => match std::fs::remove_file(&path) {
+                Ok(()) => pruned += 1,
+                Err(e) => error!("couldn't prune archive file {path:?}: {e}"),
+            },
+            Ok(_) => {}
+            Err(e) => error!("couldn't tell how old archive file {path:?} is: {e}"),
+        }
+    }
+    Ok(pruned)
+}
+
+/// Right away, then every so often: throw away whatever's older than `retention` says to keep from the archive in
+/// `archive_dir` and the `database`. Never finishes.
+pub async fn retention_task(
+    retention: Retention,
+    archive_dir: Option<PathBuf>,
+    database: Option<Database>,
+) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let (Some(keep_days), Some(archive_dir)) = (retention.archive_days, archive_dir.clone())
+        {
+            match tokio::task::spawn_blocking(move || prune_archive(&archive_dir, keep_days)).await
+            {
+                Ok(Ok(0)) => {}
+                Ok(Ok(pruned)) => {
+                    info!("pruned {pruned} archive files older than {keep_days} days")
+                }
+                Ok(Err(e)) => error!("couldn't prune the archive: {e}"),
+                Err(e) => error!("archive pruning task failed: {e}"),
+            }
+        }
+        if let (Some(keep_days), Some(database)) = (retention.database_days, database.clone()) {
+            let cutoff = Utc::now()
+                - chrono::Duration::from_std(days(keep_days)).unwrap_or(chrono::Duration::MAX);
+            match tokio::task::spawn_blocking(move || database.prune_shifts_before(cutoff)).await {
+                Ok(Ok(0)) => {}
+                Ok(Ok(pruned)) => {
+                    info!("pruned {pruned} shifts older than {keep_days} days from the database")
+                }
+                Ok(Err(e)) => error!("couldn't prune the database: {e}"),
+                Err(e) => error!("database pruning task failed: {e}"),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_prune_archive_only_prunes_old_archive_files() {
+    let archive_dir =
+        std::env::temp_dir().join(format!("lamprey-retention-{}", std::process::id()));
+    std::fs::remove_dir_all(&archive_dir).ok();
+    std::fs::create_dir_all(&archive_dir).unwrap();
+    let old = SystemTime::now() - days(NonZeroU32::new(3).unwrap());
+    for (file_name, modified) in [
+        ("RACE5-20210704T123456_events.jsonl", Some(old)),
+        ("20210705T123456_between_shifts_events.jsonl", Some(old)),
+        ("20210706T123456_events.jsonl", None),
+        // not ours, so not ours to delete
+        ("notes.txt", Some(old)),
+    ] {
+        let file = std::fs::File::create(archive_dir.join(file_name)).unwrap();
+        if let Some(modified) = modified {
+            file.set_modified(modified).unwrap();
+        }
+    }
+
+    assert_eq!(
+        prune_archive(&archive_dir, NonZeroU32::new(2).unwrap()).unwrap(),
+        2
+    );
+    let mut left: Vec<String> = std::fs::read_dir(&archive_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    left.sort();
+    assert_eq!(left, ["20210706T123456_events.jsonl", "notes.txt"]);
+
+    std::fs::remove_dir_all(&archive_dir).ok();
+}
@@ -0,0 +1,331 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use racers_ledger_datatypes::shift_summary::{RaceSummary, ShiftSummary};
+pub use racers_ledger_datatypes::shift_summary::{SalvageTally, Totals};
+use racers_ledger_datatypes::{ExitCause, SalvageEvent};
+
+/// What `SetRACEInfoEvent` told us about the RACE being run.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RaceInfo {
+    pub seed: i64,
+    pub version: i64,
+    pub start_date_utc: String,
+    pub max_total_value: i64,
+    pub max_salvage_mass: i64,
+}
+
+impl RaceInfo {
+    /// The RACE info in a `SetRACEInfoEvent`, if that's what this is.
+    pub fn from_event(salvage_event: &SalvageEvent) -> Option<Self> {
+        match salvage_event {
+            SalvageEvent::SetRACEInfoEvent {
+                seed,
+                version,
+                start_date_utc,
+                max_total_value,
+                max_salvage_mass,
+                ..
+            } => Some(RaceInfo {
+                seed: *seed,
+                version: *version,
+                start_date_utc: start_date_utc.clone(),
+                max_total_value: *max_total_value,
+                max_salvage_mass: *max_salvage_mass,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// `achieved` as a percentage of `max`, unless there's no max to speak of.
+fn percent_of(achieved: f64, max: i64) -> Option<f64> {
+    (max > 0).then(|| achieved / max as f64 * 100.0)
+}
+
+/// Running totals for a shift, kept up to date as events come in so late-joining clients don't need to have seen
+/// every event since the start of the shift.
+#[derive(Serialize, Debug, Clone)]
+pub struct ShiftAggregate {
+    pub started: DateTime<Utc>,
+    pub ended: Option<DateTime<Utc>>,
+    /// What the `EndShiftEvent` said about why the shift ended
+    pub exit_cause: Option<ExitCause>,
+    pub race_info: Option<RaceInfo>,
+    /// Latest `TimeTickEvent`'s current time, in seconds (always counts up)
+    pub current_time: Option<f64>,
+    /// Latest `TimeTickEvent`'s max time, in seconds
+    pub max_time: Option<f64>,
+    #[serde(flatten)]
+    pub totals: SalvageTally,
+    pub by_salvaged_by: BTreeMap<String, SalvageTally>,
+    pub by_category: BTreeMap<String, SalvageTally>,
+}
+
+impl ShiftAggregate {
+    pub fn new(started: DateTime<Utc>) -> Self {
+        ShiftAggregate {
+            started,
+            ended: None,
+            exit_cause: None,
+            race_info: None,
+            current_time: None,
+            max_time: None,
+            totals: SalvageTally::default(),
+            by_salvaged_by: BTreeMap::new(),
+            by_category: BTreeMap::new(),
+        }
+    }
+
+    /// Fold one event into the running totals. Anything that isn't about this shift is ignored.
+    pub fn record(&mut self, salvage_event: &SalvageEvent) {
+        match salvage_event {
+            SalvageEvent::ShiftSalvageLogEntry {
+                mass,
+                categories,
+                salvaged_by,
+                value,
+                destroyed,
+                ..
+            } => {
+                self.totals.add(*value, *mass, *destroyed);
+                self.by_salvaged_by
+                    .entry(salvaged_by.clone())
+                    .or_default()
+                    .add(*value, *mass, *destroyed);
+                for category in categories {
+                    self.by_category
+                        .entry(category.clone())
+                        .or_default()
+                        .add(*value, *mass, *destroyed);
+                }
+            }
+            SalvageEvent::SetRACEInfoEvent { .. } => {
+                self.race_info = RaceInfo::from_event(salvage_event);
+            }
+            SalvageEvent::TimeTickEvent {
+                current_time,
+                max_time,
+                ..
+            } => {
+                self.current_time = Some(*current_time);
+                self.max_time = Some(*max_time);
+            }
+            SalvageEvent::EndShiftEvent {
+                exit_cause,
+                system_time,
+            } => {
+                self.ended = Some(*system_time);
+                self.exit_cause = exit_cause.clone();
+            }
+            _ => {}
+        }
+    }
+
+    /// Where the RACE is headed, for the `racePaceEvent` that follows every `TimeTickEvent` in one: what's been
+    /// salvaged so far, scaled up from the time gone by to the whole shift. `None` if this isn't a RACE, the shift is
+    /// over, or no time has gone by yet.
+    pub fn race_pace(&self, system_time: DateTime<Utc>) -> Option<SalvageEvent> {
+        let race_info = self.race_info.as_ref()?;
+        let (current_time, max_time) = (self.current_time?, self.max_time?);
+        if self.ended.is_some() || current_time <= 0.0 {
+            return None;
+        }
+        // ticks can run a little past the end of the shift, and there's nothing left to project by then
+        let scale = (max_time / current_time).max(1.0);
+        let Totals { value, mass, .. } = self.totals.salvaged;
+        Some(SalvageEvent::RacePaceEvent {
+            current_time,
+            max_time,
+            value,
+            mass,
+            projected_value: value * scale,
+            projected_mass: mass * scale,
+            percent_of_max_total_value: percent_of(value, race_info.max_total_value),
+            percent_of_max_salvage_mass: percent_of(mass, race_info.max_salvage_mass),
+            system_time,
+        })
+    }
+
+    /// Everything we know about the shift, boiled down for the `shiftSummaryEvent` that follows its end. `None` until
+    /// it's actually ended.
+    pub fn summary(&self) -> Option<ShiftSummary> {
+        let ended = self.ended?;
+        Some(ShiftSummary {
+            started: self.started,
+            ended,
+            duration: (ended - self.started).num_milliseconds() as f64 / 1000.0,
+            game_time: self.current_time,
+            salvaged: self.totals.salvaged.clone(),
+            destroyed: self.totals.destroyed.clone(),
+            by_salvaged_by: self.by_salvaged_by.clone(),
+            by_category: self.by_category.clone(),
+            exit_cause: self.exit_cause.clone(),
+            race: self.race_info.as_ref().map(|race_info| RaceSummary {
+                seed: race_info.seed,
+                version: race_info.version,
+                start_date_utc: race_info.start_date_utc.clone(),
+                max_total_value: race_info.max_total_value,
+                max_salvage_mass: race_info.max_salvage_mass,
+                percent_of_max_total_value: percent_of(
+                    self.totals.salvaged.value,
+                    race_info.max_total_value,
+                ),
+                percent_of_max_salvage_mass: percent_of(
+                    self.totals.salvaged.mass,
+                    race_info.max_salvage_mass,
+                ),
+            }),
+        })
+    }
+}
+
+#[test]
+fn test_shift_aggregate_totals() {
+    let now = Utc::now();
+    let entry = |salvaged_by: &str, categories: &[&str], value, mass, destroyed| {
+        SalvageEvent::ShiftSalvageLogEntry {
+            object_name: "Thing".into(),
+            mass,
+            categories: categories.iter().map(|c| c.to_string()).collect(),
+            salvaged_by: salvaged_by.into(),
+            value,
+            mass_based_value: false,
+            destroyed,
+            game_time: 1.0,
+            system_time: now,
+        }
+    };
+    let mut shift = ShiftAggregate::new(now);
+    shift.record(&entry("Furnace", &["Ferrous"], 100.0, 10.0, false));
+    shift.record(&entry("Furnace", &["Ferrous", "Reactor"], 50.0, 5.0, true));
+    shift.record(&entry("Processor", &["Aluminum"], 25.0, 1.0, false));
+    shift.record(&SalvageEvent::TimeTickEvent {
+        current_time: 12.0,
+        max_time: 900.0,
+        system_time: now,
+    });
+
+    assert_eq!(
+        shift.totals.salvaged,
+        Totals {
+            items: 2,
+            value: 125.0,
+            mass: 11.0
+        }
+    );
+    assert_eq!(
+        shift.totals.destroyed,
+        Totals {
+            items: 1,
+            value: 50.0,
+            mass: 5.0
+        }
+    );
+    assert_eq!(shift.by_salvaged_by["Furnace"].salvaged.items, 1);
+    assert_eq!(shift.by_salvaged_by["Furnace"].destroyed.value, 50.0);
+    assert_eq!(shift.by_category["Ferrous"].salvaged.value, 100.0);
+    assert_eq!(shift.by_category["Ferrous"].destroyed.value, 50.0);
+    assert_eq!(shift.by_category["Reactor"].destroyed.items, 1);
+    assert_eq!(shift.current_time, Some(12.0));
+    assert_eq!(shift.max_time, Some(900.0));
+    assert!(shift.ended.is_none());
+}
+
+#[test]
+fn test_race_pace() {
+    let now = Utc::now();
+    let tick = |current_time| SalvageEvent::TimeTickEvent {
+        current_time,
+        max_time: 900.0,
+        system_time: now,
+    };
+    let mut shift = ShiftAggregate::new(now);
+    shift.record(&tick(0.0));
+    shift.record(&SalvageEvent::ShiftSalvageLogEntry {
+        object_name: "Thing".into(),
+        mass: 30.0,
+        categories: vec![],
+        salvaged_by: "Furnace".into(),
+        value: 100.0,
+        mass_based_value: false,
+        destroyed: false,
+        game_time: 1.0,
+        system_time: now,
+    });
+    shift.record(&tick(300.0));
+    // not a RACE (yet)
+    assert!(shift.race_pace(now).is_none());
+    shift.record(&SalvageEvent::SetRACEInfoEvent {
+        seed: 1,
+        version: 2,
+        start_date_utc: "whenever".into(),
+        max_total_value: 1000,
+        max_salvage_mass: 300,
+        system_time: now,
+    });
+    match shift.race_pace(now) {
+        Some(SalvageEvent::RacePaceEvent {
+            projected_value,
+            projected_mass,
+            percent_of_max_total_value,
+            percent_of_max_salvage_mass,
+            ..
+        }) => {
+            assert_eq!(projected_value, 300.0);
+            assert_eq!(projected_mass, 90.0);
+            assert_eq!(percent_of_max_total_value, Some(10.0));
+            assert_eq!(percent_of_max_salvage_mass, Some(10.0));
+        }
+        other => panic!("expected a RACE pace, got {:?}", other),
+    }
+    // once time's up, there's nothing more coming
+    shift.record(&tick(901.0));
+    assert!(matches!(
+        shift.race_pace(now),
+        Some(SalvageEvent::RacePaceEvent { projected_value, .. }) if projected_value == 100.0
+    ));
+}
+
+#[test]
+fn test_shift_summary() {
+    let started = Utc::now();
+    let ended = started + chrono::Duration::seconds(90);
+    let mut shift = ShiftAggregate::new(started);
+    shift.record(&SalvageEvent::SetRACEInfoEvent {
+        seed: 1,
+        version: 2,
+        start_date_utc: "whenever".into(),
+        max_total_value: 1000,
+        max_salvage_mass: 0,
+        system_time: started,
+    });
+    shift.record(&SalvageEvent::ShiftSalvageLogEntry {
+        object_name: "Thing".into(),
+        mass: 10.0,
+        categories: vec!["Ferrous".into()],
+        salvaged_by: "Furnace".into(),
+        value: 250.0,
+        mass_based_value: false,
+        destroyed: false,
+        game_time: 1.0,
+        system_time: started,
+    });
+    assert!(shift.summary().is_none());
+    shift.record(&SalvageEvent::EndShiftEvent {
+        exit_cause: Some(ExitCause::Abort),
+        system_time: ended,
+    });
+
+    let summary = shift.summary().unwrap();
+    assert_eq!(summary.duration, 90.0);
+    assert_eq!(summary.exit_cause, Some(ExitCause::Abort));
+    assert_eq!(summary.salvaged.value, 250.0);
+    assert_eq!(summary.by_category["Ferrous"].salvaged.items, 1);
+    let race = summary.race.unwrap();
+    assert_eq!(race.percent_of_max_total_value, Some(25.0));
+    // a RACE without a max mass doesn't get a percentage of it
+    assert_eq!(race.percent_of_max_salvage_mass, None);
+}
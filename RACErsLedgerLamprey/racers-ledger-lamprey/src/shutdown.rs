@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use futures::future;
+use log::{info, warn};
+use tokio::task::JoinHandle;
+
+use racers_ledger_datatypes::{SalvageEvent, ShutdownCause};
+
+use super::envelope::LedgerEventsSender;
+use super::Clients;
+
+/// How long the sinks get to work through whatever's still in the broadcast channel.
+const SINK_FLUSH_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long proxy clients get to hang up once they have their close frame. Windows only gives us a few seconds
+/// after the console closes, so neither of these can be very generous.
+const CLIENT_CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Why the lamprey is going away, and what proxy clients get in their close frame because of it.
+#[derive(Debug)]
+pub struct Shutdown {
+    pub cause: ShutdownCause,
+    pub code: u16,
+    pub reason: String,
+}
+
+impl Shutdown {
+    pub fn new(cause: ShutdownCause, code: u16, reason: impl Into<String>) -> Self {
+        Shutdown {
+            cause,
+            code,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Wait until we're asked to stop: Ctrl-C anywhere, SIGTERM on unix, the console window closing on Windows. Returns
+/// which one it was, for the logs.
+pub async fn signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut interrupt = signal(SignalKind::interrupt()).expect("couldn't listen for SIGINT");
+        let mut terminate = signal(SignalKind::terminate()).expect("couldn't listen for SIGTERM");
+        tokio::select! {
+            _ = interrupt.recv() => "SIGINT",
+            _ = terminate.recv() => "SIGTERM",
+        }
+    }
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows;
+        let mut ctrl_c = windows::ctrl_c().expect("couldn't listen for Ctrl-C");
+        let mut ctrl_break = windows::ctrl_break().expect("couldn't listen for Ctrl-Break");
+        let mut ctrl_close =
+            windows::ctrl_close().expect("couldn't listen for the console closing");
+        tokio::select! {
+            _ = ctrl_c.recv() => "Ctrl-C",
+            _ = ctrl_break.recv() => "Ctrl-Break",
+            _ = ctrl_close.recv() => "console closing",
+        }
+    }
+}
+
+/// Wind everything down without losing anything on the way: stop `source` (whatever's feeding us events), say why
+/// with a `LampreyShutdownEvent`, let the `sinks` finish off what's already in the broadcast channel so the archive
+/// and database get all of it and proxy clients get every event before their close frame, then close every proxy
+/// client and give them a moment to hang up. `ledger_events_sender` has to be the last one left (besides the one
+/// `source` has), or the sinks never run out of events.
+pub async fn wind_down(
+    source: JoinHandle<()>,
+    ledger_events_sender: LedgerEventsSender,
+    sinks: Vec<JoinHandle<()>>,
+    clients: &Clients,
+    shutdown: &Shutdown,
+) {
+    source.abort();
+    source.await.ok();
+    ledger_events_sender.send_own(SalvageEvent::LampreyShutdownEvent {
+        cause: shutdown.cause,
+        code: shutdown.code,
+        reason: shutdown.reason.clone(),
+        system_time: Utc::now(),
+    });
+    drop(ledger_events_sender);
+
+    info!("waiting for the sinks to catch up");
+    if tokio::time::timeout(SINK_FLUSH_TIMEOUT, future::join_all(sinks))
+        .await
+        .is_err()
+    {
+        warn!("sinks didn't catch up within {SINK_FLUSH_TIMEOUT:?}, some events might not have made it");
+    }
+
+    info!(
+        "closing proxy clients ({}: {})",
+        shutdown.code, shutdown.reason
+    );
+    super::disconnect_all_clients(clients, shutdown.code, &shutdown.reason).await;
+    let all_gone = async {
+        while !clients.read().await.is_empty() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    if tokio::time::timeout(CLIENT_CLOSE_TIMEOUT, all_gone)
+        .await
+        .is_err()
+    {
+        warn!(
+            "{} proxy clients didn't hang up within {CLIENT_CLOSE_TIMEOUT:?}, leaving without them",
+            clients.read().await.len()
+        );
+    }
+}
@@ -0,0 +1,571 @@
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+
+use racers_ledger_datatypes::{ExitCause, SalvageEvent};
+
+use super::losses::{LossTallies, ObjectLoss};
+use super::shift::{RaceInfo, SalvageTally, Totals};
+
+/// Schema migrations, in order. `PRAGMA user_version` says how many of these a database has had applied already.
+const MIGRATIONS: &[&str] = &[
+    r#"
+    CREATE TABLE shifts (
+        id INTEGER PRIMARY KEY,
+        started TEXT NOT NULL,
+        ended TEXT,
+        race_seed INTEGER,
+        race_version INTEGER,
+        race_start_date_utc TEXT,
+        race_max_total_value INTEGER,
+        race_max_salvage_mass INTEGER,
+        items_salvaged INTEGER NOT NULL DEFAULT 0,
+        value_salvaged REAL NOT NULL DEFAULT 0,
+        mass_salvaged REAL NOT NULL DEFAULT 0,
+        items_destroyed INTEGER NOT NULL DEFAULT 0,
+        value_destroyed REAL NOT NULL DEFAULT 0,
+        mass_destroyed REAL NOT NULL DEFAULT 0
+    );
+    CREATE TABLE salvage_entries (
+        id INTEGER PRIMARY KEY,
+        shift_id INTEGER NOT NULL REFERENCES shifts(id),
+        object_name TEXT NOT NULL,
+        mass REAL NOT NULL,
+        -- semicolon separated, same as the mod's _ledger.csv
+        categories TEXT NOT NULL,
+        salvaged_by TEXT NOT NULL,
+        value REAL NOT NULL,
+        mass_based_value INTEGER NOT NULL,
+        destroyed INTEGER NOT NULL,
+        game_time REAL NOT NULL,
+        system_time TEXT NOT NULL
+    );
+    CREATE INDEX salvage_entries_shift_id ON salvage_entries(shift_id);
+    CREATE INDEX shifts_race ON shifts(race_seed, race_version);
+"#,
+    // what EndShiftEvent says about why the shift ended, as the mod said it
+    "ALTER TABLE shifts ADD COLUMN exit_cause TEXT;",
+];
+
+/// How far apart two shifts' start times can be and still be the same shift, see `Database::record_shift`.
+const SAME_SHIFT_START_TOLERANCE: chrono::Duration = chrono::Duration::seconds(2);
+
+/// One shift as stored in the database.
+#[derive(Serialize, Debug, Clone)]
+pub struct StoredShift {
+    pub id: i64,
+    pub started: DateTime<Utc>,
+    pub ended: Option<DateTime<Utc>>,
+    pub exit_cause: Option<ExitCause>,
+    pub race_info: Option<RaceInfo>,
+    #[serde(flatten)]
+    pub totals: SalvageTally,
+}
+
+/// One shift with everything salvaged or destroyed in it.
+#[derive(Serialize, Debug, Clone)]
+pub struct StoredShiftWithEntries {
+    #[serde(flatten)]
+    pub shift: StoredShift,
+    /// All `ShiftSalvageLogEntry`s, in the order they happened
+    pub entries: Vec<SalvageEvent>,
+}
+
+/// SQLite database of every shift the lamprey has seen, so they can be compared later.
+///
+/// rusqlite is blocking, so everything here is too: call it from `spawn_blocking`.
+#[derive(Clone)]
+pub struct Database {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl std::fmt::Debug for Database {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Database").finish_non_exhaustive()
+    }
+}
+
+impl Database {
+    /// Open (or create) the database at `path` and bring its schema up to date.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::migrate(Connection::open(path)?)
+    }
+
+    /// In-memory database, for tests.
+    #[cfg(test)]
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::migrate(Connection::open_in_memory()?)
+    }
+
+    fn migrate(mut connection: Connection) -> rusqlite::Result<Self> {
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let transaction = connection.transaction()?;
+        for migration in MIGRATIONS.iter().skip(version) {
+            transaction.execute_batch(migration)?;
+        }
+        transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        transaction.commit()?;
+        Ok(Database {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        // a panic while holding the lock doesn't leave sqlite in a bad state, so poisoning doesn't matter to us
+        self.connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Store whatever `salvage_event` means for the shift with id `shift_id` (if we're in one), returning the id of
+    /// the shift we're in afterwards.
+    pub fn record(
+        &self,
+        shift_id: Option<i64>,
+        salvage_event: &SalvageEvent,
+    ) -> rusqlite::Result<Option<i64>> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let shift_id = record(&transaction, shift_id, salvage_event)?;
+        transaction.commit()?;
+        Ok(shift_id)
+    }
+
+    /// Store a whole shift that started at `started` from its events, all or nothing, unless we already have a shift
+    /// that started then (i.e. so importing twice doesn't double up). Returns false if we did.
+    ///
+    /// "Then" is a few seconds either way: shifts imported from the mod's files only know their start time to the
+    /// second, while the same shift recorded live has the mod's `StartShiftEvent` time, to the millisecond.
+    pub fn record_shift(
+        &self,
+        started: DateTime<Utc>,
+        salvage_events: &[SalvageEvent],
+    ) -> rusqlite::Result<bool> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let exists: bool = transaction.query_row(
+            "SELECT EXISTS (SELECT 1 FROM shifts WHERE started BETWEEN ?1 AND ?2)",
+            params![
+                started - SAME_SHIFT_START_TOLERANCE,
+                started + SAME_SHIFT_START_TOLERANCE
+            ],
+            |row| row.get(0),
+        )?;
+        if exists {
+            return Ok(false);
+        }
+        let mut shift_id = None;
+        for salvage_event in salvage_events {
+            shift_id = record(&transaction, shift_id, salvage_event)?;
+        }
+        transaction.commit()?;
+        Ok(true)
+    }
+
+    /// Delete every shift that started before `cutoff`, and everything in it. Returns how many shifts that was.
+    pub fn prune_shifts_before(&self, cutoff: DateTime<Utc>) -> rusqlite::Result<usize> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "DELETE FROM salvage_entries WHERE shift_id IN (SELECT id FROM shifts WHERE started < ?1)",
+            params![cutoff],
+        )?;
+        let pruned =
+            transaction.execute("DELETE FROM shifts WHERE started < ?1", params![cutoff])?;
+        transaction.commit()?;
+        Ok(pruned)
+    }
+
+    /// Every shift, newest first.
+    pub fn shifts(&self, limit: Option<u32>) -> rusqlite::Result<Vec<StoredShift>> {
+        let connection = self.connection();
+        let mut statement =
+            connection.prepare("SELECT * FROM shifts ORDER BY started DESC, id DESC LIMIT ?1")?;
+        let shifts = statement
+            .query_map(params![limit.map(i64::from).unwrap_or(-1)], shift_from_row)?
+            .collect();
+        shifts
+    }
+
+    /// The finished run of this RACE that salvaged the most value, with everything in it. Abandoned runs don't count.
+    pub fn personal_best(
+        &self,
+        race_seed: i64,
+        race_version: i64,
+    ) -> rusqlite::Result<Option<StoredShiftWithEntries>> {
+        let shift_id = self
+            .connection()
+            .query_row(
+                "SELECT id FROM shifts
+                 WHERE race_seed = ?1 AND race_version = ?2 AND ended IS NOT NULL
+                    AND (exit_cause IS NULL OR exit_cause != 'abort')
+                 ORDER BY value_salvaged DESC, id LIMIT 1",
+                params![race_seed, race_version],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match shift_id {
+            Some(shift_id) => self.shift(shift_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Every shift's salvage entries added up for a `LossReport`, with the `top_objects` worst destroyed objects.
+    /// SQLite does the adding up, so years of shifts don't all have to be read in for it.
+    pub fn loss_tallies(&self, top_objects: usize) -> rusqlite::Result<LossTallies> {
+        let connection = self.connection();
+        let totals_from_row = |row: &Row, first: usize| -> rusqlite::Result<Totals> {
+            Ok(Totals {
+                items: row.get(first)?,
+                value: row.get(first + 1)?,
+                mass: row.get(first + 2)?,
+            })
+        };
+        let grouped_by = |column: &str| -> rusqlite::Result<Vec<(String, bool, Totals)>> {
+            let mut statement = connection.prepare(&format!(
+                "SELECT {column}, destroyed, COUNT(*), SUM(value), SUM(mass) FROM salvage_entries
+                 GROUP BY {column}, destroyed"
+            ))?;
+            let groups = statement
+                .query_map([], |row| {
+                    Ok((row.get(0)?, row.get(1)?, totals_from_row(row, 2)?))
+                })?
+                .collect();
+            groups
+        };
+        fn tally(
+            tallies: &mut BTreeMap<String, SalvageTally>,
+            name: &str,
+            destroyed: bool,
+            totals: &Totals,
+        ) {
+            let tally = tallies.entry(name.to_string()).or_default();
+            if destroyed {
+                tally.destroyed.merge(totals);
+            } else {
+                tally.salvaged.merge(totals);
+            }
+        }
+        let mut by_salvaged_by = BTreeMap::new();
+        for (salvaged_by, destroyed, totals) in grouped_by("salvaged_by")? {
+            tally(&mut by_salvaged_by, &salvaged_by, destroyed, &totals);
+        }
+        let mut by_category = BTreeMap::new();
+        // categories are stored together, so these are every combination of them: split them up again
+        for (categories, destroyed, totals) in grouped_by("categories")? {
+            for category in categories
+                .split(';')
+                .filter(|category| !category.is_empty())
+            {
+                tally(&mut by_category, category, destroyed, &totals);
+            }
+        }
+        let mut statement = connection.prepare(
+            "SELECT object_name, COUNT(*), SUM(value), SUM(mass) FROM salvage_entries WHERE destroyed
+             GROUP BY object_name ORDER BY SUM(value) DESC, object_name LIMIT ?1",
+        )?;
+        let worst_objects = statement
+            .query_map(
+                params![i64::try_from(top_objects).unwrap_or(i64::MAX)],
+                |row| {
+                    Ok(ObjectLoss {
+                        object_name: row.get(0)?,
+                        destroyed: totals_from_row(row, 1)?,
+                    })
+                },
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(LossTallies {
+            by_salvaged_by,
+            by_category,
+            worst_objects,
+        })
+    }
+
+    pub fn shift(&self, shift_id: i64) -> rusqlite::Result<Option<StoredShiftWithEntries>> {
+        let connection = self.connection();
+        let shift = connection
+            .query_row(
+                "SELECT * FROM shifts WHERE id = ?1",
+                params![shift_id],
+                shift_from_row,
+            )
+            .optional()?;
+        let shift = match shift {
+            Some(shift) => shift,
+            None => return Ok(None),
+        };
+        let mut statement = connection
+            .prepare("SELECT * FROM salvage_entries WHERE shift_id = ?1 ORDER BY game_time, id")?;
+        let entries = statement
+            .query_map(params![shift_id], entry_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(Some(StoredShiftWithEntries { shift, entries }))
+    }
+}
+
+/// Start a new shift, returning its id.
+fn start_shift(connection: &Connection, started: DateTime<Utc>) -> rusqlite::Result<i64> {
+    connection.execute("INSERT INTO shifts (started) VALUES (?1)", params![started])?;
+    Ok(connection.last_insert_rowid())
+}
+
+fn set_race_info(
+    connection: &Connection,
+    shift_id: i64,
+    race_info: &RaceInfo,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        "UPDATE shifts SET race_seed = ?2, race_version = ?3, race_start_date_utc = ?4,
+            race_max_total_value = ?5, race_max_salvage_mass = ?6
+         WHERE id = ?1",
+        params![
+            shift_id,
+            race_info.seed,
+            race_info.version,
+            race_info.start_date_utc,
+            race_info.max_total_value,
+            race_info.max_salvage_mass
+        ],
+    )?;
+    Ok(())
+}
+
+fn end_shift(
+    connection: &Connection,
+    shift_id: i64,
+    ended: DateTime<Utc>,
+    exit_cause: Option<&ExitCause>,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        "UPDATE shifts SET ended = ?2, exit_cause = ?3 WHERE id = ?1",
+        params![
+            shift_id,
+            ended,
+            exit_cause.map(|exit_cause| exit_cause.to_string())
+        ],
+    )?;
+    Ok(())
+}
+
+/// Store a `ShiftSalvageLogEntry` and add it to its shift's totals. Anything else is ignored.
+fn add_salvage(
+    connection: &Connection,
+    shift_id: i64,
+    salvage_event: &SalvageEvent,
+) -> rusqlite::Result<()> {
+    if let SalvageEvent::ShiftSalvageLogEntry {
+        object_name,
+        mass,
+        categories,
+        salvaged_by,
+        value,
+        mass_based_value,
+        destroyed,
+        game_time,
+        system_time,
+    } = salvage_event
+    {
+        connection.execute(
+            "INSERT INTO salvage_entries (shift_id, object_name, mass, categories, salvaged_by, value,
+                mass_based_value, destroyed, game_time, system_time)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                shift_id,
+                object_name,
+                mass,
+                categories.join(";"),
+                salvaged_by,
+                value,
+                mass_based_value,
+                destroyed,
+                game_time,
+                system_time
+            ],
+        )?;
+        let totals_update = if *destroyed {
+            "UPDATE shifts SET items_destroyed = items_destroyed + 1, value_destroyed = value_destroyed + ?2,
+                mass_destroyed = mass_destroyed + ?3 WHERE id = ?1"
+        } else {
+            "UPDATE shifts SET items_salvaged = items_salvaged + 1, value_salvaged = value_salvaged + ?2,
+                mass_salvaged = mass_salvaged + ?3 WHERE id = ?1"
+        };
+        connection.execute(totals_update, params![shift_id, value, mass])?;
+    }
+    Ok(())
+}
+
+/// `Database::record`, as part of whatever transaction `connection` is in (entries and their shift's totals have to
+/// go in together).
+fn record(
+    connection: &Connection,
+    shift_id: Option<i64>,
+    salvage_event: &SalvageEvent,
+) -> rusqlite::Result<Option<i64>> {
+    match (salvage_event, shift_id) {
+        (SalvageEvent::StartShiftEvent { system_time }, _) => {
+            start_shift(connection, *system_time).map(Some)
+        }
+        (SalvageEvent::SetRACEInfoEvent { .. }, Some(shift_id)) => {
+            if let Some(race_info) = RaceInfo::from_event(salvage_event) {
+                set_race_info(connection, shift_id, &race_info)?;
+            }
+            Ok(Some(shift_id))
+        }
+        (SalvageEvent::ShiftSalvageLogEntry { .. }, Some(shift_id)) => {
+            add_salvage(connection, shift_id, salvage_event)?;
+            Ok(Some(shift_id))
+        }
+        (
+            SalvageEvent::EndShiftEvent {
+                exit_cause,
+                system_time,
+            },
+            Some(shift_id),
+        ) => {
+            end_shift(connection, shift_id, *system_time, exit_cause.as_ref())?;
+            Ok(None)
+        }
+        // same as the state updater: if the mod went away mid-shift, that shift isn't getting any more events
+        (SalvageEvent::UpstreamDisconnectedEvent { .. }, _) => Ok(None),
+        (_, shift_id) => Ok(shift_id),
+    }
+}
+
+fn shift_from_row(row: &Row) -> rusqlite::Result<StoredShift> {
+    let race_seed: Option<i64> = row.get("race_seed")?;
+    let exit_cause: Option<String> = row.get("exit_cause")?;
+    let race_info = match race_seed {
+        Some(seed) => Some(RaceInfo {
+            seed,
+            version: row.get("race_version")?,
+            start_date_utc: row.get("race_start_date_utc")?,
+            max_total_value: row.get("race_max_total_value")?,
+            max_salvage_mass: row.get("race_max_salvage_mass")?,
+        }),
+        None => None,
+    };
+    Ok(StoredShift {
+        id: row.get("id")?,
+        started: row.get("started")?,
+        ended: row.get("ended")?,
+        exit_cause: exit_cause.map(ExitCause::from),
+        race_info,
+        totals: SalvageTally {
+            salvaged: Totals {
+                items: row.get("items_salvaged")?,
+                value: row.get("value_salvaged")?,
+                mass: row.get("mass_salvaged")?,
+            },
+            destroyed: Totals {
+                items: row.get("items_destroyed")?,
+                value: row.get("value_destroyed")?,
+                mass: row.get("mass_destroyed")?,
+            },
+        },
+    })
+}
+
+fn entry_from_row(row: &Row) -> rusqlite::Result<SalvageEvent> {
+    let categories: String = row.get("categories")?;
+    Ok(SalvageEvent::ShiftSalvageLogEntry {
+        object_name: row.get("object_name")?,
+        mass: row.get("mass")?,
+        categories: categories
+            .split(';')
+            .filter(|category| !category.is_empty())
+            .map(String::from)
+            .collect(),
+        salvaged_by: row.get("salvaged_by")?,
+        value: row.get("value")?,
+        mass_based_value: row.get("mass_based_value")?,
+        destroyed: row.get("destroyed")?,
+        game_time: row.get("game_time")?,
+        system_time: row.get("system_time")?,
+    })
+}
+
+#[test]
+fn test_database_round_trip() {
+    let database = Database::open_in_memory().unwrap();
+    let now = Utc::now();
+    let entry = |value, destroyed| SalvageEvent::ShiftSalvageLogEntry {
+        object_name: "Thing".into(),
+        mass: 2.0,
+        categories: vec!["Ferrous".into(), "Salvage".into()],
+        salvaged_by: "Furnace".into(),
+        value,
+        mass_based_value: true,
+        destroyed,
+        game_time: 1.0,
+        system_time: now,
+    };
+    let salvage_events = [
+        // before any shift, so nowhere to put it
+        entry(1000.0, false),
+        SalvageEvent::StartShiftEvent { system_time: now },
+        SalvageEvent::SetRACEInfoEvent {
+            seed: 1234,
+            version: 4,
+            start_date_utc: "2021-07-01".into(),
+            max_total_value: 1_000_000,
+            max_salvage_mass: 50_000,
+            system_time: now,
+        },
+        entry(100.0, false),
+        entry(50.0, true),
+        entry(25.0, false),
+        SalvageEvent::EndShiftEvent {
+            exit_cause: Some(ExitCause::Complete),
+            system_time: now,
+        },
+    ];
+    let mut shift_id = None;
+    for salvage_event in &salvage_events {
+        shift_id = database.record(shift_id, salvage_event).unwrap();
+    }
+    assert!(shift_id.is_none());
+
+    let shifts = database.shifts(None).unwrap();
+    assert_eq!(shifts.len(), 1);
+    assert!(shifts[0].ended.is_some());
+    assert_eq!(shifts[0].exit_cause, Some(ExitCause::Complete));
+    assert_eq!(shifts[0].race_info.as_ref().unwrap().seed, 1234);
+    assert_eq!(shifts[0].totals.salvaged.value, 125.0);
+    assert_eq!(shifts[0].totals.destroyed.items, 1);
+
+    let shift = database.shift(shifts[0].id).unwrap().unwrap();
+    assert_eq!(shift.entries.len(), 3);
+    match &shift.entries[0] {
+        SalvageEvent::ShiftSalvageLogEntry { categories, .. } => {
+            assert_eq!(
+                categories,
+                &vec!["Ferrous".to_string(), "Salvage".to_string()]
+            )
+        }
+        other => panic!("expected a salvage entry, got {:?}", other),
+    }
+    assert!(database.shift(shifts[0].id + 1).unwrap().is_none());
+
+    let best = database.personal_best(1234, 4).unwrap().unwrap();
+    assert_eq!(best.shift.id, shifts[0].id);
+    assert_eq!(best.entries.len(), 3);
+    assert!(database.personal_best(1234, 5).unwrap().is_none());
+
+    assert_eq!(database.prune_shifts_before(now).unwrap(), 0);
+    assert_eq!(
+        database
+            .prune_shifts_before(now + chrono::Duration::seconds(1))
+            .unwrap(),
+        1
+    );
+    assert!(database.shifts(None).unwrap().is_empty());
+    assert!(database.shift(shifts[0].id).unwrap().is_none());
+}
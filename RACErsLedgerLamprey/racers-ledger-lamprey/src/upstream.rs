@@ -0,0 +1,325 @@
+use std::time::Duration;
+
+use async_tungstenite::{
+    tokio::connect_async,
+    tungstenite::{protocol::CloseFrame, Message},
+};
+use chrono::Utc;
+use futures::prelude::*;
+use log::{error, info, trace, warn};
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use racers_ledger_datatypes::{SalvageEvent, ShutdownCause, MOD_EVENT_TYPES, PROTOCOL_VERSION};
+
+use super::envelope::LedgerEventsSender;
+use super::shutdown::Shutdown;
+
+/// What the mod told us about itself in its `WelcomeEvent`, and whether we can work with that.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct UpstreamInfo {
+    /// `None` for mods from before protocol versions
+    pub protocol_version: Option<u32>,
+    pub mod_version: Option<String>,
+    pub game_build: Option<String>,
+    /// Event types the mod says it might send that we don't know about. They'll be passed along as-is.
+    pub unknown_event_types: Vec<String>,
+    /// Anything about the mod that might cause trouble. Empty if all is well.
+    pub problems: Vec<String>,
+}
+
+impl UpstreamInfo {
+    /// Check the mod's `WelcomeEvent` against what we expect. `None` if this isn't a `WelcomeEvent`.
+    pub fn from_welcome(salvage_event: &SalvageEvent) -> Option<Self> {
+        let (protocol_version, mod_version, game_build, supported_event_types) = match salvage_event
+        {
+            SalvageEvent::WelcomeEvent {
+                protocol_version,
+                mod_version,
+                game_build,
+                supported_event_types,
+                ..
+            } => (
+                protocol_version,
+                mod_version,
+                game_build,
+                supported_event_types,
+            ),
+            _ => return None,
+        };
+        let mut problems = vec![];
+        match protocol_version {
+            None => problems.push(format!(
+                "the mod didn't say what protocol version it speaks, so it's probably older than this lamprey \
+                 (protocol v{PROTOCOL_VERSION}): update the mod"
+            )),
+            Some(protocol_version) if *protocol_version > PROTOCOL_VERSION => problems.push(format!(
+                "the mod speaks protocol v{protocol_version}, which is newer than this lamprey's \
+                 v{PROTOCOL_VERSION}: update the lamprey"
+            )),
+            Some(protocol_version) if *protocol_version < PROTOCOL_VERSION => problems.push(format!(
+                "the mod speaks protocol v{protocol_version}, which is older than this lamprey's \
+                 v{PROTOCOL_VERSION}: update the mod"
+            )),
+            Some(_) => {}
+        }
+        let unknown_event_types = supported_event_types
+            .iter()
+            .flatten()
+            .filter(|event_type| !MOD_EVENT_TYPES.contains(&event_type.as_str()))
+            .cloned()
+            .collect();
+        Some(UpstreamInfo {
+            protocol_version: *protocol_version,
+            mod_version: mod_version.clone(),
+            game_build: game_build.clone(),
+            unknown_event_types,
+            problems,
+        })
+    }
+}
+
+/// Exponential backoff between attempts to reach the mod websocket.
+#[derive(Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    /// How long to wait before the next attempt. Doubles every call until it hits `max`.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    /// Call this once we've successfully connected so the next outage starts from a short delay again.
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+/// Why we stopped reading from the mod websocket.
+enum Disconnect {
+    /// The mod said goodbye properly (with this close frame, if it sent one), which means the game is going away.
+    Closed(Option<CloseFrame>),
+    /// The connection went away without a close frame (or errored out), the mod might come back.
+    Dropped(String),
+}
+
+/// Keeps the mod<->lamprey websocket alive, retrying with exponential backoff until the mod tells us it's closing (or
+/// forever, if we're supposed to `persist` across game sessions).
+/// (when referring to this connection, we should call this "mod websocket" for consistency...)
+#[tracing::instrument(skip(ledger_events_sender, shutdown_tx))]
+pub async fn mod_websocket_task(
+    connect_destination: String,
+    max_reconnect_delay: Duration,
+    persist: bool,
+    ledger_events_sender: LedgerEventsSender,
+    shutdown_tx: oneshot::Sender<Shutdown>,
+) {
+    let mut backoff = Backoff::new(Duration::from_millis(500), max_reconnect_delay);
+    loop {
+        let (websocketstream, response) = match connect_async(connect_destination.as_str()).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                let delay = backoff.next_delay();
+                warn!("can't connect to {connect_destination} ({e}), retrying in {delay:?}");
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+        };
+        info!("connected to server");
+        info!("response code: {}", response.status());
+        backoff.reset();
+        ledger_events_sender.send_own(SalvageEvent::UpstreamConnectedEvent {
+            system_time: Utc::now(),
+        });
+
+        let (_, websocket_rx) = websocketstream.split();
+        match read_mod_websocket(websocket_rx, &ledger_events_sender).await {
+            Disconnect::Closed(close_frame) => {
+                // server died, let's tell our clients (the same thing the mod told us) and die too, unless we're
+                // sticking around for the next game
+                let code = close_frame
+                    .as_ref()
+                    .map_or(1000, |close_frame| close_frame.code.into());
+                let reason = close_frame
+                    .map(|close_frame| close_frame.reason.to_string())
+                    .filter(|reason| !reason.is_empty())
+                    .unwrap_or_else(|| "game closed! (probably)".to_string());
+                info!("the mod closed the connection ({code}: {reason})");
+                ledger_events_sender.send_own(SalvageEvent::UpstreamDisconnectedEvent {
+                    reason: reason.clone(),
+                    close_code: Some(code),
+                    system_time: Utc::now(),
+                });
+                if persist {
+                    let delay = backoff.next_delay();
+                    info!("waiting for the game to come back, reconnecting in {delay:?}");
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+                shutdown_tx
+                    .send(Shutdown::new(ShutdownCause::GameExited, code, reason))
+                    .expect("somehow failed sending the shutdown signal lmao");
+                return;
+            }
+            Disconnect::Dropped(reason) => {
+                let delay = backoff.next_delay();
+                error!("lost the mod websocket ({reason}), reconnecting in {delay:?}");
+                ledger_events_sender.send_own(SalvageEvent::UpstreamDisconnectedEvent {
+                    reason,
+                    close_code: None,
+                    system_time: Utc::now(),
+                });
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// Pump messages from the mod websocket into the broadcast channel until it goes away, one way or another.
+async fn read_mod_websocket<S>(
+    mut websocket_rx: S,
+    ledger_events_sender: &LedgerEventsSender,
+) -> Disconnect
+where
+    S: Stream<Item = Result<Message, async_tungstenite::tungstenite::Error>> + Unpin,
+{
+    loop {
+        let msg = match websocket_rx.next().await {
+            None => return Disconnect::Dropped("connection ended without a close frame".into()),
+            Some(Err(e)) => return Disconnect::Dropped(e.to_string()),
+            Some(Ok(msg)) => msg,
+        };
+
+        trace!("received message {msg}");
+        match msg {
+            Message::Text(string) => {
+                trace!("trying to convert msg to object...");
+                let event: Result<SalvageEvent, serde_json::Error> =
+                    serde_json::from_str(string.as_str());
+                match event {
+                    Ok(salvage_event @ SalvageEvent::WelcomeEvent { .. }) => {
+                        if let Some(upstream_info) = UpstreamInfo::from_welcome(&salvage_event) {
+                            info!("the mod says hello: {upstream_info:?}");
+                            for problem in &upstream_info.problems {
+                                warn!("{problem}");
+                            }
+                            if !upstream_info.unknown_event_types.is_empty() {
+                                warn!(
+                                    "the mod might send events we don't know about, they'll be passed along as-is: {:?}",
+                                    upstream_info.unknown_event_types
+                                );
+                            }
+                        }
+                        ledger_events_sender.send(salvage_event);
+                    }
+                    Ok(salvage_event @ SalvageEvent::Unknown(_)) => {
+                        match salvage_event.malformed() {
+                            // nothing that was waiting on it (the shift ending, say) is going to hear about it
+                            Some(e) => error!(
+                                "the mod sent a malformed {} ({e}), passing it along as-is: {string}",
+                                salvage_event.event_type()
+                            ),
+                            // probably the mod is newer than we are, pass it along and let clients figure it out
+                            None => warn!(
+                                "the mod sent a {} we don't recognize, passing it along as-is: {string}",
+                                salvage_event.event_type()
+                            ),
+                        }
+                        ledger_events_sender.send(salvage_event);
+                    }
+                    Ok(salvage_event) => {
+                        ledger_events_sender.send(salvage_event);
+                    }
+                    Err(e) => error!("the mod sent something that isn't even JSON ({e}): {string}"),
+                }
+                // when the mod sends a burst it's all buffered already and we'd never have to wait for the socket, so
+                // let the sinks have a go before we lap them in the broadcast channel
+                tokio::task::yield_now().await;
+            }
+            Message::Ping(data) => {
+                trace!("received ping! (data: {data:?})");
+            }
+            Message::Pong(data) => {
+                trace!("received pong! (data: {data:?}");
+            }
+            Message::Binary(data) => {
+                trace!("received binary data: {data:?}")
+            }
+            Message::Close(close_frame) => {
+                trace!("received close! (close frame: {close_frame:#?})");
+                return Disconnect::Closed(close_frame);
+            }
+            Message::Frame(data) => {
+                trace!("I have no idea what happened now -- klaernie. Got data: {data:?}")
+            }
+        }
+    }
+}
+
+#[test]
+fn test_backoff_doubles_until_max() {
+    let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(3));
+    assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+    assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+    assert_eq!(backoff.next_delay(), Duration::from_secs(3));
+    assert_eq!(backoff.next_delay(), Duration::from_secs(3));
+    backoff.reset();
+    assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+}
+
+#[test]
+fn test_upstream_info_from_welcome() {
+    let welcome =
+        |protocol_version, supported_event_types: Option<Vec<&str>>| SalvageEvent::WelcomeEvent {
+            msg: "hello new client!".into(),
+            protocol_version,
+            mod_version: Some("1.8.0.0".into()),
+            game_build: None,
+            supported_event_types: supported_event_types
+                .map(|types| types.into_iter().map(String::from).collect()),
+            system_time: None,
+        };
+    let all_good = UpstreamInfo::from_welcome(&welcome(
+        Some(PROTOCOL_VERSION),
+        Some(MOD_EVENT_TYPES.to_vec()),
+    ))
+    .unwrap();
+    assert!(all_good.problems.is_empty());
+    assert!(all_good.unknown_event_types.is_empty());
+    assert_eq!(all_good.mod_version.as_deref(), Some("1.8.0.0"));
+
+    let newer = UpstreamInfo::from_welcome(&welcome(
+        Some(PROTOCOL_VERSION + 1),
+        Some(vec!["startShiftEvent", "shinyNewEvent"]),
+    ))
+    .unwrap();
+    assert_eq!(newer.problems.len(), 1);
+    assert_eq!(newer.unknown_event_types, vec!["shinyNewEvent".to_string()]);
+
+    assert_eq!(
+        UpstreamInfo::from_welcome(&welcome(None, None))
+            .unwrap()
+            .problems
+            .len(),
+        1
+    );
+    assert!(UpstreamInfo::from_welcome(&SalvageEvent::StartShiftEvent {
+        system_time: Utc::now()
+    })
+    .is_none());
+}
@@ -0,0 +1,599 @@
+// End-to-end tests: a real lamprey talking to the mock mod, with us as a proxy client. No game required.
+use std::{
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use async_tungstenite::{tokio::connect_async, tungstenite::Message};
+use futures::prelude::*;
+use racers_ledger_datatypes::{ExitCause, SalvageEvent, ShutdownCause};
+
+/// Kills the child process if the test bails out early, so we don't leave lampreys lying around.
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        self.0.kill().ok();
+    }
+}
+
+fn lamprey(args: &[&str]) -> KillOnDrop {
+    KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_racers-ledger-lamprey"))
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("couldn't start the lamprey"),
+    )
+}
+
+/// Connect to the proxy websocket, waiting for the lamprey to come up if it hasn't yet.
+async fn connect_proxy(
+    listen_port: u16,
+) -> async_tungstenite::WebSocketStream<async_tungstenite::tokio::ConnectStream> {
+    let url = format!("ws://127.0.0.1:{listen_port}/api/v0/racers-ledger-proxy");
+    for _ in 0..50 {
+        if let Ok((websocket, _)) = connect_async(url.as_str()).await {
+            return websocket;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("lamprey never started listening on {}", url);
+}
+
+#[tokio::test]
+async fn test_lamprey_proxies_a_whole_shift_from_the_mock_mod() {
+    let (mod_port, listen_port) = (42180, 42181);
+    // start the lamprey first, it should keep retrying until the mod shows up
+    let mut lamprey_process = lamprey(&[
+        "connect",
+        &mod_port.to_string(),
+        &listen_port.to_string(),
+        "--max-reconnect-delay",
+        "1",
+    ]);
+    let mut proxy = connect_proxy(listen_port).await;
+    let _mock_mod = lamprey(&[
+        "mock-mod",
+        &mod_port.to_string(),
+        "--speed",
+        "instant",
+        "--seed",
+        "1",
+        "--items",
+        "10",
+        "--shift-seconds",
+        "5",
+        "--race",
+    ]);
+
+    let mut event_types = vec![];
+    let mut last_event = None;
+    let mut summary = None;
+    let close_frame = tokio::time::timeout(Duration::from_secs(30), async {
+        while let Some(msg) = proxy.next().await {
+            match msg.expect("proxy websocket errored") {
+                Message::Text(text) => {
+                    let salvage_event: SalvageEvent = serde_json::from_str(text.as_str())
+                        .unwrap_or_else(|e| panic!("lamprey sent us junk ({}): {}", e, text));
+                    let json = serde_json::to_value(&salvage_event).unwrap();
+                    event_types.push(json["type"].as_str().unwrap().to_string());
+                    if let SalvageEvent::ShiftSummaryEvent { summary: s, .. } = &salvage_event {
+                        summary = Some(s.clone());
+                    }
+                    last_event = Some(salvage_event);
+                }
+                Message::Close(close_frame) => return close_frame,
+                _ => {}
+            }
+        }
+        None
+    })
+    .await
+    .expect("timed out waiting for the shift to finish");
+
+    let count = |event_type: &str| event_types.iter().filter(|t| *t == event_type).count();
+    // the lamprey says hi before anything else
+    assert_eq!(event_types.first().unwrap(), "lampreyWelcomeEvent");
+    assert_eq!(count("lampreyWelcomeEvent"), 1);
+    assert_eq!(count("upstreamConnectedEvent"), 1);
+    assert_eq!(count("welcomeEvent"), 1);
+    assert_eq!(count("startShiftEvent"), 1);
+    assert_eq!(count("setRACEInfoEvent"), 1);
+    assert_eq!(count("shiftSalvageLogEntry"), 10);
+    assert_eq!(count("timeTickEvent"), 5);
+    // it's a RACE, so every tick comes with how it's going
+    assert_eq!(count("racePaceEvent"), 5);
+    assert!(event_types
+        .windows(2)
+        .filter(|pair| pair[1] == "racePaceEvent")
+        .all(|pair| pair[0] == "timeTickEvent"));
+    assert_eq!(count("endShiftEvent"), 1);
+    assert_eq!(count("shiftSummaryEvent"), 1);
+    assert_eq!(count("upstreamDisconnectedEvent"), 1);
+    // which has the lamprey's take on the whole shift
+    let summary = summary.unwrap();
+    assert_eq!(summary.salvaged.items + summary.destroyed.items, 10);
+    assert_eq!(summary.exit_cause, Some(ExitCause::Complete));
+    let race = summary.race.expect("the mock mod's RACE info went missing");
+    assert!(race.percent_of_max_total_value.is_some());
+    // then the lamprey says why it's leaving, and passes on what the mod said when it left
+    assert!(
+        matches!(
+            &last_event,
+            Some(SalvageEvent::LampreyShutdownEvent {
+                cause: ShutdownCause::GameExited,
+                code: 1000,
+                reason,
+                ..
+            }) if reason == "lamprey server closing"
+        ),
+        "{:?}",
+        last_event
+    );
+    let close_frame = close_frame.expect("lamprey closed without a close frame");
+    assert_eq!(u16::from(close_frame.code), 1000);
+    assert_eq!(close_frame.reason.as_str(), "lamprey server closing");
+
+    // and once the mod closes, the lamprey should pack up too
+    for _ in 0..50 {
+        if let Some(status) = lamprey_process.0.try_wait().unwrap() {
+            assert!(status.success());
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("lamprey didn't exit after the mod closed");
+}
+
+#[tokio::test]
+async fn test_late_client_catches_up_on_the_shift() {
+    let (mod_port, listen_port) = (42182, 42183);
+    let _lamprey = lamprey(&[
+        "connect",
+        &mod_port.to_string(),
+        &listen_port.to_string(),
+        "--max-reconnect-delay",
+        "1",
+    ]);
+    let mut early_proxy = connect_proxy(listen_port).await;
+    let _mock_mod = lamprey(&[
+        "mock-mod",
+        &mod_port.to_string(),
+        "--speed",
+        "instant",
+        "--items",
+        "3",
+        "--shift-seconds",
+        "2",
+    ]);
+
+    // wait for the shift to be over before the late client even shows up
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while let Some(msg) = early_proxy.next().await {
+            if let Message::Text(text) = msg.expect("proxy websocket errored") {
+                // not just looking for the string, the welcomes mention endShiftEvent too
+                let json: serde_json::Value = serde_json::from_str(text.as_str()).unwrap();
+                if json["type"] == "endShiftEvent" {
+                    return;
+                }
+            }
+        }
+        panic!("proxy closed before the shift ended");
+    })
+    .await
+    .expect("timed out waiting for the shift to finish");
+
+    let url = format!(
+        "ws://127.0.0.1:{}/api/v0/racers-ledger-proxy?since=shift_start",
+        listen_port
+    );
+    let (mut late_proxy, _) = connect_async(url.as_str()).await.unwrap();
+    let mut event_types = vec![];
+    while let Some(Ok(Message::Text(text))) = late_proxy.next().await {
+        let json: serde_json::Value = serde_json::from_str(text.as_str()).unwrap();
+        event_types.push(json["type"].as_str().unwrap().to_string());
+        if event_types.last().unwrap() == "endShiftEvent" {
+            break;
+        }
+    }
+    assert_eq!(event_types[0], "lampreyWelcomeEvent");
+    assert_eq!(event_types[1], "startShiftEvent");
+    assert_eq!(
+        event_types
+            .iter()
+            .filter(|t| *t == "shiftSalvageLogEntry")
+            .count(),
+        3
+    );
+    assert_eq!(event_types.last().unwrap(), "endShiftEvent");
+}
+
+#[tokio::test]
+async fn test_client_only_gets_what_it_subscribed_to() {
+    let (mod_port, listen_port) = (42184, 42185);
+    let _lamprey = lamprey(&[
+        "connect",
+        &mod_port.to_string(),
+        &listen_port.to_string(),
+        "--max-reconnect-delay",
+        "1",
+    ]);
+    // subscribe in the query string, then change our mind over the socket
+    let url = format!(
+        "ws://127.0.0.1:{}/api/v0/racers-ledger-proxy?types=timeTickEvent",
+        listen_port
+    );
+    connect_proxy(listen_port).await;
+    let (mut proxy, _) = connect_async(url.as_str()).await.unwrap();
+    proxy
+        .send(Message::text(
+            r#"{"type":"subscribe","events":["shiftSalvageLogEntry","endShiftEvent"]}"#,
+        ))
+        .await
+        .unwrap();
+    // give the lamprey a moment to process that before anything happens
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let _mock_mod = lamprey(&[
+        "mock-mod",
+        &mod_port.to_string(),
+        "--speed",
+        "instant",
+        "--items",
+        "4",
+        "--shift-seconds",
+        "3",
+    ]);
+
+    let mut event_types = vec![];
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while let Some(Ok(Message::Text(text))) = proxy.next().await {
+            let json: serde_json::Value = serde_json::from_str(text.as_str()).unwrap();
+            event_types.push(json["type"].as_str().unwrap().to_string());
+        }
+    })
+    .await
+    .expect("timed out waiting for the shift to finish");
+
+    // the welcome isn't part of the stream, so subscriptions don't filter it out
+    let mut expected = vec!["lampreyWelcomeEvent"];
+    expected.extend(["shiftSalvageLogEntry"; 4]);
+    expected.push("endShiftEvent");
+    assert_eq!(event_types, expected);
+}
+
+#[tokio::test]
+async fn test_unknown_events_are_passed_along() {
+    let (mod_port, listen_port) = (42186, 42187);
+    let unknown = r#"{"type":"shinyNewEvent","systemTime":"2021-07-04T12:34:56Z","shiny":[1,2,3]}"#;
+    let script = std::env::temp_dir().join(format!("lamprey-unknown-{}.jsonl", std::process::id()));
+    std::fs::write(
+        &script,
+        format!(
+            "{}\n{}\n{}\n",
+            r#"{"type":"startShiftEvent","systemTime":"2021-07-04T12:34:56Z"}"#,
+            unknown,
+            r#"{"type":"endShiftEvent","systemTime":"2021-07-04T12:34:57Z"}"#
+        ),
+    )
+    .unwrap();
+    let _lamprey = lamprey(&[
+        "connect",
+        &mod_port.to_string(),
+        &listen_port.to_string(),
+        "--max-reconnect-delay",
+        "1",
+    ]);
+    let mut proxy = connect_proxy(listen_port).await;
+    let _mock_mod = lamprey(&[
+        "mock-mod",
+        &mod_port.to_string(),
+        "--speed",
+        "instant",
+        "--script",
+        script.to_str().unwrap(),
+    ]);
+
+    let mut received = vec![];
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while let Some(Ok(Message::Text(text))) = proxy.next().await {
+            received.push(serde_json::from_str::<serde_json::Value>(text.as_str()).unwrap());
+        }
+    })
+    .await
+    .expect("timed out waiting for the shift to finish");
+    std::fs::remove_file(&script).ok();
+
+    let unknown: serde_json::Value = serde_json::from_str(unknown).unwrap();
+    assert!(
+        received.contains(&unknown),
+        "never got {} in {:?}",
+        unknown,
+        received
+    );
+    // the mod's last word, then the lamprey's
+    let event_types: Vec<_> = received.iter().map(|json| json["type"].clone()).collect();
+    assert_eq!(
+        event_types[event_types.len() - 4..],
+        [
+            "endShiftEvent",
+            "shiftSummaryEvent",
+            "upstreamDisconnectedEvent",
+            "lampreyShutdownEvent"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_envelopes_have_sequence_numbers_and_can_be_resent() {
+    let (mod_port, listen_port) = (42188, 42189);
+    let _lamprey = lamprey(&[
+        "connect",
+        &mod_port.to_string(),
+        &listen_port.to_string(),
+        "--max-reconnect-delay",
+        "1",
+    ]);
+    connect_proxy(listen_port).await;
+    let url = format!(
+        "ws://127.0.0.1:{}/api/v0/racers-ledger-proxy?envelope=true",
+        listen_port
+    );
+    let (mut proxy, _) = connect_async(url.as_str()).await.unwrap();
+    let _mock_mod = lamprey(&[
+        "mock-mod",
+        &mod_port.to_string(),
+        "--speed",
+        "instant",
+        "--items",
+        "5",
+        "--shift-seconds",
+        "2",
+    ]);
+
+    let mut envelopes: Vec<serde_json::Value> = vec![];
+    let mut resent = vec![];
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while let Some(Ok(Message::Text(text))) = proxy.next().await {
+            let envelope: serde_json::Value = serde_json::from_str(text.as_str()).unwrap();
+            assert!(envelope["receivedTime"].is_string(), "{}", envelope);
+            let seq = envelope["seq"].as_u64().unwrap();
+            if envelopes
+                .last()
+                .is_some_and(|last| seq != 0 && seq <= last["seq"].as_u64().unwrap())
+            {
+                resent.push(envelope);
+                continue;
+            }
+            if envelope["event"]["type"] == "endShiftEvent" {
+                // pretend we missed the first two salvages and ask for them again
+                let from = envelopes
+                    .iter()
+                    .find(|e| e["event"]["type"] == "shiftSalvageLogEntry")
+                    .unwrap()["seq"]
+                    .as_u64()
+                    .unwrap();
+                proxy
+                    .send(Message::text(format!(
+                        r#"{{"type":"resend","from":{},"to":{}}}"#,
+                        from,
+                        from + 1
+                    )))
+                    .await
+                    .unwrap();
+            }
+            envelopes.push(envelope);
+        }
+    })
+    .await
+    .expect("timed out waiting for the shift to finish");
+
+    // the welcome isn't part of the stream, everything after it counts up one at a time
+    assert_eq!(envelopes[0]["seq"], 0);
+    assert_eq!(envelopes[0]["event"]["type"], "lampreyWelcomeEvent");
+    let seqs: Vec<u64> = envelopes[1..]
+        .iter()
+        .map(|e| e["seq"].as_u64().unwrap())
+        .collect();
+    assert!(seqs.windows(2).all(|w| w[1] == w[0] + 1), "{:?}", seqs);
+    assert_eq!(resent.len(), 2, "{:?}", resent);
+    for envelope in &resent {
+        assert!(
+            envelopes.contains(envelope),
+            "{} was never sent before",
+            envelope
+        );
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_sigterm_closes_clients_and_exits() {
+    let (mod_port, listen_port) = (42190, 42191);
+    // no mod at all, the lamprey just keeps trying to connect until it's told to stop
+    let mut lamprey_process =
+        lamprey(&["connect", &mod_port.to_string(), &listen_port.to_string()]);
+    let mut proxy = connect_proxy(listen_port).await;
+    match proxy.next().await {
+        Some(Ok(Message::Text(text))) => assert!(text.contains("lampreyWelcomeEvent"), "{}", text),
+        msg => panic!("expected a welcome, got {:?}", msg),
+    }
+
+    let killed = Command::new("kill")
+        .args(["-TERM", &lamprey_process.0.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    let mut told_why = false;
+    let close_frame = tokio::time::timeout(Duration::from_secs(10), async {
+        while let Some(msg) = proxy.next().await {
+            match msg.expect("proxy websocket errored") {
+                Message::Text(text) => {
+                    told_why |= matches!(
+                        serde_json::from_str(text.as_str()),
+                        Ok(SalvageEvent::LampreyShutdownEvent {
+                            cause: ShutdownCause::Stopped,
+                            code: 1001,
+                            ..
+                        })
+                    );
+                }
+                Message::Close(close_frame) => return close_frame,
+                _ => {}
+            }
+        }
+        None
+    })
+    .await
+    .expect("timed out waiting for the close frame")
+    .expect("lamprey closed without a close frame");
+    assert_eq!(u16::from(close_frame.code), 1001);
+    assert_eq!(close_frame.reason.as_str(), "lamprey shutting down");
+    assert!(told_why, "never got a lampreyShutdownEvent");
+
+    for _ in 0..50 {
+        if let Some(status) = lamprey_process.0.try_wait().unwrap() {
+            assert!(status.success());
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("lamprey didn't exit after SIGTERM");
+}
+
+#[tokio::test]
+async fn test_persistent_lamprey_outlives_the_game() {
+    let (mod_port, listen_port) = (42192, 42193);
+    let mut lamprey_process = lamprey(&[
+        "connect",
+        &mod_port.to_string(),
+        &listen_port.to_string(),
+        "--max-reconnect-delay",
+        "1",
+        "--persist",
+    ]);
+    let mut proxy = connect_proxy(listen_port).await;
+
+    let mut event_types = vec![];
+    // two games in a row, one shift each, with the same proxy client the whole time
+    for game in 0..2 {
+        let mut mock_mod = lamprey(&[
+            "mock-mod",
+            &mod_port.to_string(),
+            "--speed",
+            "instant",
+            "--seed",
+            &game.to_string(),
+            "--items",
+            "3",
+            "--shift-seconds",
+            "2",
+        ]);
+        tokio::time::timeout(Duration::from_secs(30), async {
+            while let Some(msg) = proxy.next().await {
+                match msg.expect("proxy websocket errored") {
+                    Message::Text(text) => {
+                        let json: serde_json::Value = serde_json::from_str(text.as_str()).unwrap();
+                        let event_type = json["type"].as_str().unwrap().to_string();
+                        event_types.push(event_type.clone());
+                        if event_type == "upstreamDisconnectedEvent" {
+                            assert_eq!(json["closeCode"], 1000);
+                            return;
+                        }
+                    }
+                    msg => panic!("the lamprey shouldn't be going anywhere, got {:?}", msg),
+                }
+            }
+            panic!("proxy closed when the game did");
+        })
+        .await
+        .expect("timed out waiting for the game to close");
+        mock_mod.0.wait().unwrap();
+        assert!(lamprey_process.0.try_wait().unwrap().is_none());
+    }
+
+    let count = |event_type: &str| event_types.iter().filter(|t| *t == event_type).count();
+    assert_eq!(count("lampreyWelcomeEvent"), 1);
+    assert_eq!(count("upstreamConnectedEvent"), 2);
+    assert_eq!(count("startShiftEvent"), 2);
+    assert_eq!(count("shiftSalvageLogEntry"), 6);
+    assert_eq!(count("endShiftEvent"), 2);
+    assert_eq!(count("shiftSummaryEvent"), 2);
+    assert_eq!(count("lampreyShutdownEvent"), 0);
+}
+
+#[tokio::test]
+async fn test_second_run_of_a_race_races_the_first() {
+    let (mod_port, listen_port) = (42194, 42195);
+    let database =
+        std::env::temp_dir().join(format!("lamprey-ghost-{}.sqlite", std::process::id()));
+    std::fs::remove_file(&database).ok();
+    let _lamprey = lamprey(&[
+        "connect",
+        &mod_port.to_string(),
+        &listen_port.to_string(),
+        "--max-reconnect-delay",
+        "1",
+        "--persist",
+        "--database",
+        database.to_str().unwrap(),
+    ]);
+    let mut proxy = connect_proxy(listen_port).await;
+
+    // the same RACE twice, run exactly the same way both times
+    let mut runs = vec![];
+    for _ in 0..2 {
+        let mut mock_mod = lamprey(&[
+            "mock-mod",
+            &mod_port.to_string(),
+            "--speed",
+            "instant",
+            "--seed",
+            "7",
+            "--items",
+            "5",
+            "--shift-seconds",
+            "3",
+            "--race",
+        ]);
+        let mut run = vec![];
+        tokio::time::timeout(Duration::from_secs(30), async {
+            while let Some(Ok(Message::Text(text))) = proxy.next().await {
+                let json: serde_json::Value = serde_json::from_str(text.as_str()).unwrap();
+                if json["type"] == "upstreamDisconnectedEvent" {
+                    return;
+                }
+                run.push(json);
+            }
+            panic!("proxy closed when the game did");
+        })
+        .await
+        .expect("timed out waiting for the game to close");
+        mock_mod.0.wait().unwrap();
+        runs.push(run);
+    }
+    std::fs::remove_file(&database).ok();
+
+    let of_type = |run: &[serde_json::Value], event_type: &str| -> Vec<serde_json::Value> {
+        run.iter()
+            .filter(|json| json["type"] == event_type)
+            .cloned()
+            .collect()
+    };
+    // nothing to race the first time around
+    assert!(of_type(&runs[0], "ghostDeltaEvent").is_empty());
+    let deltas = of_type(&runs[1], "ghostDeltaEvent");
+    assert_eq!(deltas.len(), 3, "{:?}", runs[1]);
+    for delta in &deltas {
+        assert_eq!(delta["valueDelta"], 0.0, "{}", delta);
+    }
+    assert_eq!(
+        deltas.last().unwrap()["value"],
+        deltas.last().unwrap()["ghostFinalValue"]
+    );
+    let splits = of_type(&runs[1], "ghostSplitEvent");
+    assert!(!splits.is_empty());
+    for split in &splits {
+        assert_eq!(split["timeDelta"], 0.0, "{}", split);
+    }
+}
@@ -38,7 +38,7 @@ namespace RACErsLedger
                 CurrentShift.EndShift(ExitCause);
                 var shift = CurrentShift;
 
-                var @event = new EndShiftEvent();
+                var @event = new EndShiftEvent(ExitCause);
                 Plugin.LampreyManager.SendEvent(@event);
 
                 StringBuilder sb = new StringBuilder();